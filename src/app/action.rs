@@ -1,7 +1,8 @@
 use std::borrow::Cow;
-use std::io::Write;
 use std::ops::DerefMut;
 use std::path::{Path, PathBuf};
+use std::sync::atomic::{AtomicBool, Ordering};
+use std::sync::Arc;
 use std::time::Instant;
 
 use crate::app::window::AppWindow;
@@ -12,9 +13,13 @@ use tokio::time::Interval;
 
 use super::window::Target;
 use super::{
-    canvas::Bound2,
+    canvas::{Bound2, RecordingState},
     capture::CaptureDevice,
+    capture_backend::{CaptureTarget, WindowSelector},
+    config::{AnimationFormat, RecordingSettings},
+    error::{CaptureError, CaptureResult},
     event::{Event, UserEvent, WindowEventHandler},
+    progress::{CaptureProgress, RecordingProgress},
     window::{WindowHashMap, WindowIDDHashMap},
 };
 use chrono::Duration;
@@ -24,52 +29,209 @@ pub trait Execute<A: ActionContext> {
     fn execute(&self, ctx: &mut A);
 }
 
+/// Delay between dispatching a capture action and taking the capture
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(unused)]
+pub enum Countdown {
+    /// `CaptureConfig::countdown_seconds`
+    Default,
+    Seconds(u32),
+}
+
+/// The format of a recording action, `None` for `CaptureConfig::animation_format`
+pub type Format = Option<AnimationFormat>;
+
+/// The frame rate, limits and looping of a recording action, `None` for
+/// `CaptureConfig::recording`
+pub type Settings = Option<RecordingSettings>;
+
 #[derive(Debug, Clone, Copy)]
 #[allow(unused)]
 pub enum Action {
     ImageCapture,
-    DoImageCapture(Bound2),
-    DoGifCapture(Bound2),
-    GifCapture,
+    DoImageCapture(Bound2, Countdown),
+    DoGifCapture(Bound2, Countdown, Format, Settings),
+    /// Captures the window under the point clicked in the selector
+    DoWindowImageCapture((i32, i32), Countdown),
+    DoWindowGifCapture((i32, i32), Countdown, Format, Settings),
+    /// Captures the window of `CaptureConfig::window`
+    WindowImageCapture,
+    /// Records the window of `CaptureConfig::window`
+    WindowGifCapture(Format, Settings),
+    /// Selects the region of a recording
+    GifCapture(Format, Settings),
+    /// Holds the GIF recording, the paused time is left out of it
+    PauseGifCapture,
+    ResumeGifCapture,
     StopGifCaptureAndSave,
     StopGifCaptureAndDrop,
+    /// Saves what the instant replay recorded so far
+    SaveReplay,
+    /// Selects the region of a timelapse
+    Timelapse,
+    DoTimelapse(Bound2, Countdown),
+    /// Ends the running timelapse and saves it
+    StopTimelapse,
     Suspend,
 }
 
+impl Action {
+    ///
+    /// The countdown of a capture action, `None` for the other actions
+    fn countdown(&self) -> Option<Countdown> {
+        match self {
+            Self::DoImageCapture(_, countdown)
+            | Self::DoGifCapture(_, countdown, ..)
+            | Self::DoWindowImageCapture(_, countdown)
+            | Self::DoWindowGifCapture(_, countdown, ..)
+            | Self::DoTimelapse(_, countdown) => Some(*countdown),
+            _ => None,
+        }
+    }
+
+    ///
+    /// The same action without a countdown
+    fn immediate(self) -> Self {
+        let now = Countdown::Seconds(0);
+        match self {
+            Self::DoImageCapture(rect, _) => Self::DoImageCapture(rect, now),
+            Self::DoGifCapture(rect, _, format, settings) => {
+                Self::DoGifCapture(rect, now, format, settings)
+            }
+            Self::DoWindowImageCapture(pos, _) => Self::DoWindowImageCapture(pos, now),
+            Self::DoWindowGifCapture(pos, _, format, settings) => {
+                Self::DoWindowGifCapture(pos, now, format, settings)
+            }
+            Self::DoTimelapse(rect, _) => Self::DoTimelapse(rect, now),
+            action => action,
+        }
+    }
+}
+
 impl<A> Execute<A> for Action
 where
     A: ActionContext,
 {
     fn execute(&self, ctx: &mut A) {
-        match self {
-            Self::ImageCapture => ctx.invoke_image_capture(),
-            Self::GifCapture => ctx.invoke_gif_capture(),
-            Self::Suspend => ctx.suspend(),
-            Self::DoGifCapture(rect) => {
-                ctx.do_gif_capture(*rect, 15, 30f64);
-            }
-            Self::DoImageCapture(rect) => {
-                ctx.do_image_capture(*rect);
+        if let Some(countdown) = self.countdown() {
+            let seconds = ctx.countdown_seconds(countdown);
+            if seconds > 0 {
+                ctx.start_countdown(self.immediate(), seconds);
+                return;
+            }
+        }
+        let result = match self {
+            Self::ImageCapture => {
+                ctx.invoke_image_capture();
+                Ok(())
+            }
+            Self::GifCapture(format, settings) => {
+                ctx.invoke_gif_capture(*format, *settings);
+                Ok(())
+            }
+            Self::Suspend => {
+                ctx.suspend();
+                Ok(())
+            }
+            Self::DoGifCapture(rect, _, format, settings) => {
+                ctx.do_gif_capture(CaptureTarget::Region(*rect), *settings, *format)
+            }
+            Self::DoImageCapture(rect, _) => {
+                let result = ctx.do_image_capture(CaptureTarget::Region(*rect));
+                ctx.suspend();
+                result
+            }
+            Self::DoWindowImageCapture((x, y), _) => {
+                // the selector must be out of the way before the window is read
+                ctx.suspend();
+                match ctx.find_window(&WindowSelector::At(*x, *y)) {
+                    Some(window) => ctx.do_image_capture(CaptureTarget::Window(window)),
+                    None => Ok(()),
+                }
+            }
+            Self::DoWindowGifCapture((x, y), _, format, settings) => {
                 ctx.suspend();
+                match ctx.find_window(&WindowSelector::At(*x, *y)) {
+                    Some(window) => {
+                        ctx.do_gif_capture(CaptureTarget::Window(window), *settings, *format)
+                    }
+                    None => Ok(()),
+                }
+            }
+            Self::WindowImageCapture => ctx
+                .configured_window()
+                .and_then(|window| ctx.do_image_capture(CaptureTarget::Window(window))),
+            Self::WindowGifCapture(format, settings) => {
+                ctx.configured_window().and_then(|window| {
+                    ctx.do_gif_capture(CaptureTarget::Window(window), *settings, *format)
+                })
+            }
+            Self::PauseGifCapture => {
+                ctx.pause_gif_capture();
+                Ok(())
+            }
+            Self::ResumeGifCapture => {
+                ctx.resume_gif_capture();
+                Ok(())
             }
             Self::StopGifCaptureAndSave => {
                 ctx.suspend();
+                Ok(())
             }
             Self::StopGifCaptureAndDrop => {
+                ctx.stop_gif_capture_and_drop();
+                ctx.suspend();
+                Ok(())
+            }
+            Self::SaveReplay => ctx.save_replay(),
+            Self::Timelapse => {
+                ctx.invoke_timelapse();
+                Ok(())
+            }
+            Self::DoTimelapse(rect, _) => {
+                // the timelapse runs in the background, the selector is done
                 ctx.suspend();
+                ctx.do_timelapse(CaptureTarget::Region(*rect))
             }
+            Self::StopTimelapse => {
+                ctx.stop_timelapse();
+                Ok(())
+            }
+        };
+        if let Err(error) = result {
+            ctx.report_error(error);
         }
     }
 }
 
 pub trait ActionContext {
     fn invoke_image_capture(&mut self);
-    fn invoke_gif_capture(&mut self);
-    fn do_image_capture(&mut self, rect: Bound2);
-    fn do_gif_capture(&mut self, rect: Bound2, fps: u32, duration: f64);
+    fn invoke_gif_capture(&mut self, format: Format, settings: Settings);
+    fn invoke_timelapse(&mut self);
+    fn find_window(&mut self, selector: &WindowSelector) -> Option<u64>;
+    /// Looks up the window of `CaptureConfig::window`
+    fn configured_window(&mut self) -> CaptureResult<u64>;
+    fn do_image_capture(&mut self, target: CaptureTarget) -> CaptureResult<()>;
+    fn do_gif_capture(
+        &mut self,
+        target: CaptureTarget,
+        settings: Settings,
+        format: Format,
+    ) -> CaptureResult<()>;
     fn suspend(&mut self);
+    fn pause_gif_capture(&mut self);
+    fn resume_gif_capture(&mut self);
     fn stop_gif_capture_and_save(&mut self);
     fn stop_gif_capture_and_drop(&mut self);
+    fn save_replay(&mut self) -> CaptureResult<()>;
+    fn do_timelapse(&mut self, target: CaptureTarget) -> CaptureResult<()>;
+    fn stop_timelapse(&mut self);
+    /// Lets the user know that an action failed
+    fn report_error(&mut self, error: CaptureError);
+    /// Seconds to count down before a capture with `countdown`
+    fn countdown_seconds(&self, countdown: Countdown) -> u32;
+    /// Executes `action` once `seconds` are counted down, unless suspended before
+    fn start_countdown(&mut self, action: Action, seconds: u32);
 }
 
 pub struct AppContext<'a> {
@@ -77,6 +239,8 @@ pub struct AppContext<'a> {
     pub capture_device: &'a mut CaptureDevice,
     pub window_hash: &'a mut WindowHashMap,
     pub window_id_hash: &'a mut WindowIDDHashMap,
+    /// Cancel flag of the running countdown
+    pub countdown: &'a mut Option<Arc<AtomicBool>>,
 }
 
 impl<'a> AppContext<'a> {
@@ -107,6 +271,65 @@ impl<'a> AppContext<'a> {
     pub fn check_file_exists<T: AsRef<std::ffi::OsStr>>(&self, path: T) -> bool {
         Path::new(&path).is_file()
     }
+
+    ///
+    /// Updates the recording indicator of the selector
+    fn notify_recording(&self, state: RecordingState) {
+        let event = Event::Recording(state);
+        let user_event = UserEvent::new(
+            Target::Action,
+            Target::Window(AppWindow::RegionSelectorCanvasWindow),
+            event,
+        );
+        self.event_proxy.send_event(user_event);
+    }
+
+    ///
+    /// Callback for the capture tasks, reporting whether the file meant for
+    /// `path` was written
+    fn save_encoded(
+        &self,
+        path: PathBuf,
+    ) -> Box<dyn FnOnce(CaptureResult<PathBuf>) + Send + 'static> {
+        let proxy = self.event_proxy.clone();
+        Box::new(move |saved| {
+            let progress = match saved {
+                Ok(path) => CaptureProgress::Finished(path),
+                Err(error) => CaptureProgress::Failed(path, Arc::new(error)),
+            };
+            send_progress(&proxy, progress);
+        })
+    }
+
+    ///
+    /// Callback for the capture task, publishing how the recording goes
+    fn report_progress(&self) -> Box<dyn FnMut(RecordingProgress) + Send + 'static> {
+        let proxy = self.event_proxy.clone();
+        Box::new(move |progress| send_progress(&proxy, CaptureProgress::Recording(progress)))
+    }
+}
+
+fn send_progress(proxy: &EventLoopProxy<UserEvent>, progress: CaptureProgress) {
+    let event = Event::Progress(progress);
+    if let Err(closed) =
+        proxy.send_event(UserEvent::new(Target::Action, Target::Application, event))
+    {
+        log::warn!("dropped {:?}", closed.0.event);
+    }
+}
+
+///
+/// Sends a failed capture to the application, which tells the user about it
+fn report_capture_error(proxy: &EventLoopProxy<UserEvent>, error: CaptureError) {
+    let error = Arc::new(error);
+    let event = Event::CaptureFailed(error.clone());
+    if proxy
+        .send_event(UserEvent::new(Target::Action, Target::Application, event))
+        .is_err()
+    {
+        // the event loop is gone, the log is all that is left
+        log::error!("{}", error);
+    }
 }
 
 impl<'a> ActionContext for AppContext<'a> {
@@ -124,10 +347,22 @@ impl<'a> ActionContext for AppContext<'a> {
 
     ///
     /// Invokes the GIF image capture canvas for the selection
-    fn invoke_gif_capture(&mut self) {
+    fn invoke_gif_capture(&mut self, format: Format, settings: Settings) {
         // debug!("invoke_gif_capture");
         //
-        let event = Event::InvokeRegionSelector(Action::GifCapture);
+        let event = Event::InvokeRegionSelector(Action::GifCapture(format, settings));
+        let user_event = UserEvent::new(
+            Target::Action,
+            Target::Window(AppWindow::RegionSelectorCanvasWindow),
+            event,
+        );
+        self.event_proxy.send_event(user_event);
+    }
+
+    ///
+    /// Invokes the region selector for a timelapse
+    fn invoke_timelapse(&mut self) {
+        let event = Event::InvokeRegionSelector(Action::Timelapse);
         let user_event = UserEvent::new(
             Target::Action,
             Target::Window(AppWindow::RegionSelectorCanvasWindow),
@@ -144,44 +379,164 @@ impl<'a> ActionContext for AppContext<'a> {
             .find_window(AppWindow::RegionSelectorCanvasWindow)
             .unwrap();
         target_win.set_visible(false);
+        if let Some(cancelled) = self.countdown.take() {
+            cancelled.store(true, Ordering::SeqCst);
+        }
         self.capture_device.stop_capture();
     }
 
+    ///
+    /// Find the window to capture by id, title or position
+    fn find_window(&mut self, selector: &WindowSelector) -> Option<u64> {
+        let window = self.capture_device.find_window(selector);
+        if window.is_none() {
+            log::warn!("no window matches {:?}", selector);
+        }
+        window
+    }
+
+    fn configured_window(&mut self) -> CaptureResult<u64> {
+        let selector = self.capture_device.config.window.clone().ok_or_else(|| {
+            CaptureError::InvalidParameter("no window to capture is configured".to_string())
+        })?;
+        ActionContext::find_window(self, &selector)
+            .ok_or_else(|| CaptureError::Backend(format!("no window matches {:?}", selector)))
+    }
+
     ///
     /// capture static image
-    fn do_image_capture(&mut self, rect: Bound2) {
-        let image = self.capture_device.capture_image(rect);
+    fn do_image_capture(&mut self, target: CaptureTarget) -> CaptureResult<()> {
+        let image = self.capture_device.capture_image(target)?;
         let ts = self.create_timestamp_str();
         let filename = format!("CAP_{}.png", ts);
         let mut save_path = self.get_save_path();
         save_path.push(filename);
-        image.save(save_path).unwrap();
+        image.save(save_path)?;
+        Ok(())
     }
 
     ///
     /// Capture gif image
-    fn do_gif_capture(&mut self, bound: Bound2, fps: u32, duration: f64) {
-        let rect = bound.rect();
+    fn do_gif_capture(
+        &mut self,
+        target: CaptureTarget,
+        settings: Settings,
+        format: Format,
+    ) -> CaptureResult<()> {
+        let format = format.unwrap_or(self.capture_device.config.animation_format);
+        let settings = settings.unwrap_or(self.capture_device.config.recording);
         let ts = self.create_timestamp_str();
-        let filename = format!("CAP_{}.gif", ts);
+        let filename = format!("CAP_{}.{}", ts, format.extension());
         let mut save_path = self.get_save_path();
         save_path.push(filename);
 
+        let progress_cb = self.report_progress();
+        let finished_cb = self.save_encoded(save_path.clone());
         self.capture_device.capture_gif_async(
-            bound,
-            fps,
-            duration,
-            Box::new(|encode_data| {
-                let mut encode_data = encode_data;
-                let mut image = std::fs::File::create(save_path).unwrap();
-                image.write(&mut encode_data);
-            }),
-        );
+            target,
+            settings,
+            format,
+            save_path,
+            progress_cb,
+            finished_cb,
+        )?;
+        self.notify_recording(RecordingState::Recording);
+        Ok(())
+    }
+
+    fn pause_gif_capture(&mut self) {
+        if self.capture_device.pause_capture() {
+            self.notify_recording(RecordingState::Paused);
+        }
+    }
+
+    fn resume_gif_capture(&mut self) {
+        if self.capture_device.resume_capture() {
+            self.notify_recording(RecordingState::Recording);
+        }
     }
 
     fn stop_gif_capture_and_save(&mut self) {}
 
-    fn stop_gif_capture_and_drop(&mut self) {}
+    fn stop_gif_capture_and_drop(&mut self) {
+        self.capture_device.cancel_capture();
+    }
+
+    ///
+    /// Saves the instant replay buffer as an animation
+    fn save_replay(&mut self) -> CaptureResult<()> {
+        let ts = self.create_timestamp_str();
+        let extension = self.capture_device.config.animation_format.extension();
+        let filename = format!("REPLAY_{}.{}", ts, extension);
+        let mut save_path = self.get_save_path();
+        save_path.push(filename);
+
+        let finished_cb = self.save_encoded(save_path.clone());
+        self.capture_device.save_replay(save_path, finished_cb)
+    }
+
+    ///
+    /// Starts a timelapse, saved as an animation once stopped
+    fn do_timelapse(&mut self, target: CaptureTarget) -> CaptureResult<()> {
+        let ts = self.create_timestamp_str();
+        let extension = self.capture_device.config.animation_format.extension();
+        let filename = format!("TIMELAPSE_{}.{}", ts, extension);
+        let mut save_path = self.get_save_path();
+        save_path.push(filename);
+
+        let finished_cb = self.save_encoded(save_path.clone());
+        let config = &self.capture_device.config.timelapse;
+        let (interval, playback_fps) = (config.interval, config.playback_fps);
+        self.capture_device
+            .start_timelapse(target, interval, playback_fps, save_path, finished_cb)
+    }
+
+    fn stop_timelapse(&mut self) {
+        self.capture_device.stop_timelapse();
+    }
+
+    fn report_error(&mut self, error: CaptureError) {
+        report_capture_error(self.event_proxy, error);
+    }
+
+    fn countdown_seconds(&self, countdown: Countdown) -> u32 {
+        match countdown {
+            Countdown::Default => self.capture_device.config.countdown_seconds,
+            Countdown::Seconds(seconds) => seconds,
+        }
+    }
+
+    ///
+    /// Ticks the countdown shown by the selector every second. The selector
+    /// gets out of the way at 0, before the action runs.
+    fn start_countdown(&mut self, action: Action, seconds: u32) {
+        if let Some(cancelled) = self.countdown.take() {
+            cancelled.store(true, Ordering::SeqCst);
+        }
+        let cancelled = Arc::new(AtomicBool::new(false));
+        *self.countdown = Some(cancelled.clone());
+        let proxy = self.event_proxy.clone();
+        std::thread::spawn(move || {
+            let selector = Target::Window(AppWindow::RegionSelectorCanvasWindow);
+            for remaining in (0..=seconds).rev() {
+                if cancelled.load(Ordering::SeqCst) {
+                    return;
+                }
+                let tick = UserEvent::new(Target::Action, selector, Event::Countdown(remaining));
+                if proxy.send_event(tick).is_err() {
+                    return;
+                }
+                if remaining > 0 {
+                    std::thread::sleep(std::time::Duration::from_secs(1));
+                }
+            }
+            let _ = proxy.send_event(UserEvent::build_action_event(
+                Target::Action,
+                Target::Action,
+                action,
+            ));
+        });
+    }
 }
 
 pub struct KeyBinding<T: Eq> {
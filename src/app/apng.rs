@@ -0,0 +1,233 @@
+//! Animated PNG, which keeps the full 32 bit colour of the frames that a GIF
+//! palette loses.
+//!
+//! The chunks are written by hand, as the `png` crate wants the number of
+//! frames before the first one, which a running recording does not know. The
+//! count is written once the recording ends, over the animation control chunk.
+
+use super::capture::FrameWriter;
+use super::error::CaptureResult;
+use super::frame_buffer::FramePool;
+use super::frame_delta;
+
+use image::RgbaImage;
+use std::io::{Seek, SeekFrom, Write};
+use std::time::Duration;
+
+const SIGNATURE: [u8; 8] = [0x89, b'P', b'N', b'G', b'\r', b'\n', 0x1a, b'\n'];
+
+/// `blend_op` drawing the frame over what is shown, leaving transparent pixels
+const BLEND_OVER: u8 = 1;
+const BLEND_SOURCE: u8 = 0;
+/// `dispose_op` leaving the frame shown under the next one
+const DISPOSE_NONE: u8 = 0;
+
+///
+/// Writes frames into an animated PNG. With `optimize` the frames after the
+/// first are cut to the part that changed, which is drawn over the previous
+/// frame with the unchanged pixels transparent.
+pub struct ApngWriter<W: Write + Seek> {
+    out: W,
+    /// Where the animation control chunk starts, once the header is written
+    control_at: Option<u64>,
+    frames: u32,
+    sequence: u32,
+    num_plays: u32,
+    optimize: bool,
+    /// The last frame, when optimizing
+    prev: Option<RgbaImage>,
+    pool: FramePool,
+}
+
+impl<W: Write + Seek> ApngWriter<W> {
+    ///
+    /// `num_plays` is how often the animation plays, 0 loops forever
+    pub fn new(out: W, num_plays: u32, optimize: bool, pool: FramePool) -> Self {
+        ApngWriter {
+            out,
+            control_at: None,
+            frames: 0,
+            sequence: 0,
+            num_plays,
+            optimize,
+            prev: None,
+            pool,
+        }
+    }
+
+    fn write_chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+        let mut crc = crc32fast::Hasher::new();
+        crc.update(kind);
+        crc.update(data);
+        self.out.write_all(&(data.len() as u32).to_be_bytes())?;
+        self.out.write_all(kind)?;
+        self.out.write_all(data)?;
+        self.out.write_all(&crc.finalize().to_be_bytes())
+    }
+
+    fn animation_control(&self) -> Vec<u8> {
+        let mut data = self.frames.to_be_bytes().to_vec();
+        data.extend_from_slice(&self.num_plays.to_be_bytes());
+        data
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> std::io::Result<()> {
+        self.out.write_all(&SIGNATURE)?;
+        let mut header = width.to_be_bytes().to_vec();
+        header.extend_from_slice(&height.to_be_bytes());
+        // 8 bit RGBA, deflate, adaptive filtering, not interlaced
+        header.extend_from_slice(&[8, 6, 0, 0, 0]);
+        self.write_chunk(b"IHDR", &header)?;
+        self.control_at = Some(self.out.stream_position()?);
+        self.write_chunk(b"acTL", &self.animation_control())
+    }
+
+    fn next_sequence(&mut self) -> u32 {
+        self.sequence += 1;
+        self.sequence - 1
+    }
+}
+
+impl<W: Write + Seek> FrameWriter for ApngWriter<W> {
+    fn write_frame(&mut self, image: RgbaImage, delay: Duration) -> CaptureResult<()> {
+        if self.control_at.is_none() {
+            self.write_header(image.width(), image.height())?;
+        }
+        let region = match self.prev.as_ref() {
+            Some(prev) if prev.dimensions() == image.dimensions() => changed_region(prev, &image),
+            _ => FrameRegion::whole(&image),
+        };
+
+        let mut control = self.next_sequence().to_be_bytes().to_vec();
+        for value in [region.width, region.height, region.x, region.y] {
+            control.extend_from_slice(&value.to_be_bytes());
+        }
+        // the delay is a fraction of seconds, in milliseconds here
+        let delay = delay.as_millis().min(u16::MAX as u128) as u16;
+        control.extend_from_slice(&delay.to_be_bytes());
+        control.extend_from_slice(&1000u16.to_be_bytes());
+        control.extend_from_slice(&[DISPOSE_NONE, region.blend]);
+        self.write_chunk(b"fcTL", &control)?;
+
+        let compressed = miniz_oxide::deflate::compress_to_vec_zlib(&region.filtered(), 6);
+        if self.frames == 0 {
+            self.write_chunk(b"IDAT", &compressed)?;
+        } else {
+            let mut data = self.next_sequence().to_be_bytes().to_vec();
+            data.extend_from_slice(&compressed);
+            self.write_chunk(b"fdAT", &data)?;
+        }
+        self.frames += 1;
+
+        if self.optimize {
+            if let Some(prev) = self.prev.replace(image) {
+                self.pool.recycle(prev.into_raw());
+            }
+        } else {
+            self.pool.recycle(image.into_raw());
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> CaptureResult<()> {
+        if let Some(prev) = self.prev.take() {
+            self.pool.recycle(prev.into_raw());
+        }
+        let control_at = match self.control_at {
+            Some(at) => at,
+            None => return Ok(()),
+        };
+        self.write_chunk(b"IEND", &[])?;
+        // the frame count is known now
+        self.out.seek(SeekFrom::Start(control_at))?;
+        self.write_chunk(b"acTL", &self.animation_control())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+///
+/// The rect of a frame that is written, in RGBA rows
+struct FrameRegion {
+    x: u32,
+    y: u32,
+    width: u32,
+    height: u32,
+    pixels: Vec<u8>,
+    blend: u8,
+}
+
+impl FrameRegion {
+    fn whole(image: &RgbaImage) -> Self {
+        FrameRegion {
+            x: 0,
+            y: 0,
+            width: image.width(),
+            height: image.height(),
+            pixels: image.as_raw().clone(),
+            blend: BLEND_SOURCE,
+        }
+    }
+
+    ///
+    /// The rows with the Sub filter in front, which predicts a pixel from the
+    /// one left of it and suits the flat areas of screen content
+    fn filtered(&self) -> Vec<u8> {
+        let stride = self.width as usize * 4;
+        let mut data = Vec::with_capacity((stride + 1) * self.height as usize);
+        for row in self.pixels.chunks_exact(stride) {
+            data.push(1);
+            data.extend_from_slice(&row[..4]);
+            data.extend(
+                row[4..]
+                    .iter()
+                    .zip(row.iter())
+                    .map(|(x, left)| x.wrapping_sub(*left)),
+            );
+        }
+        data
+    }
+}
+
+///
+/// The smallest rect holding the pixels of `image` that differ from `prev`.
+/// The pixels that do not are made transparent and blended over the previous
+/// frame, unless the rect has transparent pixels of its own.
+fn changed_region(prev: &RgbaImage, image: &RgbaImage) -> FrameRegion {
+    let (left, top, right, bottom) = match frame_delta::changed_bounds(prev, image) {
+        Some((x, y, width, height)) => (x, y, x + width, y + height),
+        None => {
+            // nothing changed, a transparent pixel keeps the delay
+            return FrameRegion {
+                x: 0,
+                y: 0,
+                width: 1,
+                height: 1,
+                pixels: vec![0; 4],
+                blend: BLEND_OVER,
+            };
+        }
+    };
+
+    let opaque = (top..bottom).all(|y| (left..right).all(|x| image.get_pixel(x, y)[3] == 0xff));
+    let mut pixels = Vec::with_capacity(((right - left) * (bottom - top) * 4) as usize);
+    for y in top..bottom {
+        for x in left..right {
+            let pixel = image.get_pixel(x, y);
+            if opaque && prev.get_pixel(x, y) == pixel {
+                pixels.extend_from_slice(&[0; 4]);
+            } else {
+                pixels.extend_from_slice(&pixel.0);
+            }
+        }
+    }
+    FrameRegion {
+        x: left,
+        y: top,
+        width: right - left,
+        height: bottom - top,
+        pixels,
+        blend: if opaque { BLEND_OVER } else { BLEND_SOURCE },
+    }
+}
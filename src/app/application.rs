@@ -1,12 +1,14 @@
 use std::cell::Cell;
 use std::ops::DerefMut;
 use std::path::PathBuf;
+use std::sync::{atomic::AtomicBool, Arc};
 
+use crate::platform;
 use crate::support;
 
 use glutin::event_loop::EventLoopProxy;
 use glutin::{
-    dpi::PhysicalPosition,
+    dpi::{PhysicalPosition, PhysicalSize},
     event::{
         DeviceEvent, ElementState, Event, KeyboardInput, ModifiersState, MouseButton,
         VirtualKeyCode, WindowEvent,
@@ -19,25 +21,24 @@ use glutin::{
 
 use super::{
     action::{Action, AppContext, Execute, KeyBinding},
+    canvas::Bound2,
     capture::CaptureDevice,
+    capture_backend::CaptureTarget,
+    config::{AnimationFormat, CaptureConfig},
+    coords::{DesktopLayout, MonitorInfo},
+    error::CaptureResult,
     event::{KeyInputData, MouseData, UserEvent, WindowEventHandler},
     graphics::Graphics,
     graphics_impl::opengl_impl::GraphicsOpenGLImpl,
+    progress::CaptureProgress,
     window::{AppWindow, CanvasWindow, Target, WindowHashMap, WindowIDDHashMap},
 };
 
-use raw_window_handle::{HasRawWindowHandle, RawWindowHandle, Win32Handle};
-
-#[allow(unused)]
-use windows::{
-    core::*, Data::Xml::Dom::*, Win32::Foundation::*, Win32::System::Threading::*,
-    Win32::UI::WindowsAndMessaging::*,
-};
-
 #[allow(unused)]
 pub struct ApplicationBuilder {
     app_name: String,
     config_file_path: PathBuf,
+    capture_config: CaptureConfig,
 }
 
 #[derive(Debug, Copy, Clone)]
@@ -68,6 +69,7 @@ impl ApplicationBuilder {
         ApplicationBuilder {
             app_name: "".to_owned(),
             config_file_path: PathBuf::from("".to_owned()),
+            capture_config: CaptureConfig::default(),
         }
     }
     pub fn with_name(mut self, name: &str) -> Self {
@@ -75,6 +77,12 @@ impl ApplicationBuilder {
         self
     }
 
+    #[allow(unused)]
+    pub fn with_capture_config(mut self, config: CaptureConfig) -> Self {
+        self.capture_config = config;
+        self
+    }
+
     #[allow(unused)]
     pub fn with_config_file_path<U: AsRef<PathBuf>>(mut self, path: U) -> Self {
         self.config_file_path = path.as_ref().to_owned();
@@ -82,23 +90,7 @@ impl ApplicationBuilder {
     }
 
     fn platform_config(&self, windowed_context: &ContextWrapper<PossiblyCurrent, Window>) {
-        if cfg!(windows) {
-            let handle = windowed_context.window().raw_window_handle();
-            unsafe {
-                match handle {
-                    RawWindowHandle::Win32(Win32Handle {
-                        hwnd, hinstance: _, ..
-                    }) => {
-                        let hwnd = HWND(hwnd as isize);
-                        let mut exstyle =
-                            WINDOW_EX_STYLE(GetWindowLongPtrW(hwnd, GWL_EXSTYLE) as u32);
-                        exstyle = exstyle | WS_EX_TOOLWINDOW;
-                        SetWindowLongW(hwnd, GWL_EXSTYLE, exstyle.0 as i32);
-                    }
-                    _ => (),
-                }
-            }
-        }
+        platform::config_window(windowed_context.window());
     }
 
     fn reload_keybinding_actions(&self) -> Vec<KeyBinding<VirtualKeyCode>> {
@@ -109,10 +101,60 @@ impl ApplicationBuilder {
                 key: VirtualKeyCode::Key1,
             },
             KeyBinding {
-                action: Action::GifCapture,
+                action: Action::GifCapture(None, None),
                 mods: ModifiersState::CTRL | ModifiersState::ALT,
                 key: VirtualKeyCode::Key2,
             },
+            KeyBinding {
+                action: Action::GifCapture(Some(AnimationFormat::Apng), None),
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::Key6,
+            },
+            KeyBinding {
+                action: Action::GifCapture(Some(AnimationFormat::Webp), None),
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::Key7,
+            },
+            KeyBinding {
+                action: Action::GifCapture(Some(AnimationFormat::Mp4), None),
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::Key8,
+            },
+            KeyBinding {
+                action: Action::WindowImageCapture,
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::W,
+            },
+            KeyBinding {
+                action: Action::WindowGifCapture(None, None),
+                mods: ModifiersState::CTRL | ModifiersState::ALT | ModifiersState::SHIFT,
+                key: VirtualKeyCode::W,
+            },
+            KeyBinding {
+                action: Action::PauseGifCapture,
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::P,
+            },
+            KeyBinding {
+                action: Action::ResumeGifCapture,
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::R,
+            },
+            KeyBinding {
+                action: Action::SaveReplay,
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::Key3,
+            },
+            KeyBinding {
+                action: Action::Timelapse,
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::Key4,
+            },
+            KeyBinding {
+                action: Action::StopTimelapse,
+                mods: ModifiersState::CTRL | ModifiersState::ALT,
+                key: VirtualKeyCode::Key5,
+            },
             KeyBinding {
                 action: Action::Suspend,
                 mods: ModifiersState::empty(),
@@ -121,35 +163,53 @@ impl ApplicationBuilder {
         ]
     }
 
-    fn create_graphics<'a, 'b>(
+    fn desktop_layout(&self, event_loop: &EventLoop<UserEvent>) -> DesktopLayout {
+        let monitors: Vec<_> = event_loop
+            .available_monitors()
+            .map(|monitor| {
+                let (pos, size) = (monitor.position(), monitor.size());
+                MonitorInfo {
+                    bound: Bound2::new(
+                        (pos.x, pos.y),
+                        (pos.x + size.width as i32, pos.y + size.height as i32),
+                    ),
+                    scale_factor: monitor.scale_factor(),
+                }
+            })
+            .collect();
+        assert!(!monitors.is_empty(), "Invalid monitor handle");
+        DesktopLayout::new(monitors)
+    }
+
+    fn create_graphics(
         &self,
-        windowed_context: &'a ContextWrapper<PossiblyCurrent, Window>,
-        event_loop: &'b EventLoop<UserEvent>,
+        windowed_context: &ContextWrapper<PossiblyCurrent, Window>,
     ) -> Box<dyn Graphics> {
-        use std::cell::RefCell;
+        use std::cell::{Cell, RefCell};
 
-        let monitor = event_loop
-            .available_monitors().last()
-            .expect("Invalid monitor handle");
-        let size = monitor.size();
         let render_api = support::load(windowed_context);
+        let size = windowed_context.window().inner_size();
+        render_api.viewport(size.width, size.height);
         Box::new(GraphicsOpenGLImpl {
             render_api: RefCell::new(render_api),
-            desktop_size: (size.width, size.height),
+            viewport_size: Cell::new((size.width, size.height)),
         })
     }
 
     fn create_window_context(
         &self,
         event_loop: &EventLoop<UserEvent>,
+        desktop: Bound2,
     ) -> ContextWrapper<NotCurrent, Window> {
         let wb = WindowBuilder::new()
             .with_title(self.app_name.clone())
             .with_decorations(false)
             .with_transparent(true)
-            .with_maximized(true)
+            .with_position(PhysicalPosition::new(desktop.min.0, desktop.min.1))
+            .with_inner_size(PhysicalSize::new(desktop.get_width(), desktop.get_height()))
             .with_always_on_top(false)
             .with_visible(true);
+        let wb = platform::config_window_builder(wb);
 
         let windowed_context = ContextBuilder::new()
             .with_gl_profile(glutin::GlProfile::Core)
@@ -159,14 +219,15 @@ impl ApplicationBuilder {
     }
 
     ///
-    ///Create the fullscreen transparent window for the region selector
+    ///Create the transparent window for the region selector, covering all monitors
     fn create_main_window(
         &self,
         event_loop: &EventLoop<UserEvent>,
         window: &mut WindowHashMap,
         window_index: &mut WindowIDDHashMap,
     ) {
-        let windowed_context = self.create_window_context(event_loop);
+        let layout = self.desktop_layout(event_loop);
+        let windowed_context = self.create_window_context(event_loop, layout.virtual_bound());
 
         let windowed_context = unsafe { windowed_context.make_current().expect("make current") };
 
@@ -174,7 +235,7 @@ impl ApplicationBuilder {
 
         self.platform_config(&windowed_context);
 
-        let graphics = self.create_graphics(&windowed_context, &event_loop);
+        let graphics = self.create_graphics(&windowed_context);
 
         let window_id = windowed_context.window().id();
         let win = CanvasWindow::new(
@@ -182,6 +243,7 @@ impl ApplicationBuilder {
             graphics,
             event_loop.create_proxy(),
             Target::Window(AppWindow::RegionSelectorCanvasWindow),
+            layout,
         );
 
         window_index
@@ -190,19 +252,29 @@ impl ApplicationBuilder {
         window.entry(window_id).or_insert(Box::new(win));
     }
 
-    pub fn build(self, event_loop: &EventLoop<UserEvent>) -> std::io::Result<Application> {
+    ///
+    /// Fails when the configured backend cannot capture the screen
+    pub fn build(self, event_loop: &EventLoop<UserEvent>) -> CaptureResult<Application> {
         let mut window_id_hashmap = WindowIDDHashMap::new();
         let mut window_hashmap = WindowHashMap::new();
         self.create_main_window(event_loop, &mut window_hashmap, &mut window_id_hashmap);
 
+        let capture_device = CaptureDevice::new(self.capture_config.clone())?;
+        if let Some(region) = self.capture_config.replay.region {
+            if let Err(e) = capture_device.start_replay(CaptureTarget::Region(region)) {
+                log::warn!("instant replay is not available: {}", e);
+            }
+        }
+
         let app = Application {
             app_name: self.app_name.clone(),
             event_proxy: event_loop.create_proxy(),
-            capture_device: CaptureDevice::new()?,
+            capture_device,
             keybinding_actions: self.reload_keybinding_actions(),
             windows: window_hashmap,
             windows_index: window_id_hashmap,
             state: Cell::new(InputStateWrapper::default()),
+            countdown: None,
         };
         Ok(app)
     }
@@ -217,6 +289,8 @@ pub struct Application {
     windows: WindowHashMap,
     windows_index: WindowIDDHashMap,
     state: Cell<InputStateWrapper>,
+    /// Cancel flag of the countdown before a capture, if one is running
+    countdown: Option<Arc<AtomicBool>>,
 }
 
 impl Application {
@@ -227,6 +301,7 @@ impl Application {
                 window_hash: &mut self.windows,
                 window_id_hash: &mut self.windows_index,
                 capture_device: &mut self.capture_device,
+                countdown: &mut self.countdown,
             };
             #[allow(deprecated)]
             let mods = input.modifiers;
@@ -248,8 +323,8 @@ impl Application {
     }
 
     pub fn handle_user_event(&mut self, data: UserEvent) {
-        println!("handle_user_event: {:?}", data);
-        match (data.sender, data.receiver, data.event) {
+        log::debug!("handle_user_event: {:?}", data);
+        match (data.sender, data.receiver, &data.event) {
             (_, Target::Window(app_window), _) => {
                 match app_window {
                     AppWindow::AllWindow => {
@@ -270,9 +345,25 @@ impl Application {
                     window_hash: &mut self.windows,
                     window_id_hash: &mut self.windows_index,
                     capture_device: &mut self.capture_device,
+                    countdown: &mut self.countdown,
                 };
                 action.execute(&mut app_ctx);
             }
+            (_, Target::Application, crate::app::event::Event::CaptureFailed(error)) => {
+                log::error!("capture failed: {}", error);
+            }
+            (_, Target::Application, crate::app::event::Event::Progress(progress)) => {
+                match progress {
+                    CaptureProgress::Recording(progress) => log::debug!("{:?}", progress),
+                    CaptureProgress::Finished(path) => log::info!("saved {}", path.display()),
+                    CaptureProgress::Failed(path, error) => {
+                        log::error!("capture failed: {}: {}", path.display(), error)
+                    }
+                }
+                for (_, win) in self.windows.iter_mut() {
+                    win.on_user_event(&data);
+                }
+            }
             _ => {
                 //log::warn!("Wrong User Event");
             }
@@ -293,8 +384,9 @@ impl Application {
                                 // translate mouse event
                                 let mut s = self.state.get();
                                 match event {
-                                    #[allow(unused)]
-                                    WindowEvent::Resized(physical_size) => (),
+                                    WindowEvent::Resized(physical_size) => {
+                                        window.on_resize_event(physical_size);
+                                    }
                                     WindowEvent::ModifiersChanged(modifier) => {
                                         s.mods = modifier;
                                     }
@@ -3,7 +3,7 @@ use glm::vec2;
 use glutin::{monitor::MonitorHandle, window, window::Window, ContextWrapper, PossiblyCurrent};
 use std::rc::Rc;
 
-#[derive(Debug, Copy, Clone)]
+#[derive(Debug, Copy, Clone, PartialEq, Eq)]
 pub struct Bound2 {
     pub min: (i32, i32),
     pub max: (i32, i32),
@@ -32,7 +32,35 @@ impl Bound2 {
     }
 
     pub fn empty(&self) -> bool {
-        self.get_width() <= 0 || self.get_height() <= 0
+        self.max.0 <= self.min.0 || self.max.1 <= self.min.1
+    }
+
+    ///
+    /// The smallest bound containing both
+    pub fn union(&self, other: &Bound2) -> Bound2 {
+        Bound2 {
+            min: (self.min.0.min(other.min.0), self.min.1.min(other.min.1)),
+            max: (self.max.0.max(other.max.0), self.max.1.max(other.max.1)),
+        }
+    }
+
+    ///
+    /// The overlapping part of both, `None` if they do not overlap
+    pub fn intersection(&self, other: &Bound2) -> Option<Bound2> {
+        let min = (self.min.0.max(other.min.0), self.min.1.max(other.min.1));
+        let max = (self.max.0.min(other.max.0), self.max.1.min(other.max.1));
+        if min.0 < max.0 && min.1 < max.1 {
+            Some(Bound2 { min, max })
+        } else {
+            None
+        }
+    }
+
+    pub fn translate(&self, offset: (i32, i32)) -> Bound2 {
+        Bound2 {
+            min: (self.min.0 + offset.0, self.min.1 + offset.1),
+            max: (self.max.0 + offset.0, self.max.1 + offset.1),
+        }
     }
 }
 
@@ -87,6 +115,95 @@ impl Renderable for RegionSelector {
     }
 }
 
+/// Height of the countdown digits in pixels
+const COUNTDOWN_DIGIT_HEIGHT: u32 = 160;
+
+///
+/// The seconds left until a capture starts, drawn large at the center of the
+/// selected region
+pub struct CountdownOverlay {
+    pub remaining: Option<u32>,
+    pub center: (i32, i32),
+}
+
+impl CountdownOverlay {
+    pub fn new() -> Self {
+        CountdownOverlay {
+            remaining: None,
+            center: (0, 0),
+        }
+    }
+
+    pub fn is_running(&self) -> bool {
+        self.remaining.is_some()
+    }
+}
+
+impl Renderable for CountdownOverlay {
+    fn update(&self, graphics: &dyn Graphics) {
+        if let Some(remaining) = self.remaining {
+            graphics.draw_number(
+                self.center.0,
+                self.center.1,
+                COUNTDOWN_DIGIT_HEIGHT,
+                remaining,
+            );
+        }
+    }
+}
+
+/// What the GIF recording in the selected region is doing
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum RecordingState {
+    Recording,
+    Paused,
+}
+
+/// Size of the recording indicator in pixels
+const INDICATOR_SIZE: u32 = 16;
+
+///
+/// Dot above the top left corner of the recorded region, red while recording
+/// and amber pause bars while paused
+pub struct RecordingIndicator {
+    pub state: Option<RecordingState>,
+    pub bound: Bound2,
+}
+
+impl RecordingIndicator {
+    pub fn new() -> Self {
+        RecordingIndicator {
+            state: None,
+            bound: Bound2::default(),
+        }
+    }
+}
+
+impl Renderable for RecordingIndicator {
+    fn update(&self, graphics: &dyn Graphics) {
+        let size = INDICATOR_SIZE;
+        let x = self.bound.min.0;
+        // above the region, or inside it at the top of the screen
+        let y = if self.bound.min.1 >= 2 * size as i32 {
+            self.bound.min.1 - 2 * size as i32
+        } else {
+            self.bound.min.1 + size as i32 / 2
+        };
+        match self.state {
+            Some(RecordingState::Recording) => {
+                graphics.fill_rect(x, y, size, size, (0.9, 0.1, 0.1, 0.9));
+            }
+            Some(RecordingState::Paused) => {
+                let bar = size / 3;
+                let color = (1.0, 0.7, 0.0, 0.9);
+                graphics.fill_rect(x, y, bar, size, color);
+                graphics.fill_rect(x + (size - bar) as i32, y, bar, size, color);
+            }
+            None => {}
+        }
+    }
+}
+
 pub struct Canvas {
     pub objects: Vec<Rc<dyn Renderable>>,
     pub graphics: Box<dyn Graphics>,
@@ -104,3 +221,31 @@ impl Canvas {
         }
     }
 }
+
+#[cfg(test)]
+mod canvas_test {
+    use super::*;
+
+    #[test]
+    fn bound_union_and_intersection_test() {
+        // two side by side monitors, the left one below the origin
+        let left = Bound2::new((-1920, 0), (0, 1080));
+        let right = Bound2::new((0, -200), (2560, 1240));
+        assert_eq!(left.union(&right), Bound2::new((-1920, -200), (2560, 1240)));
+
+        let region = Bound2::new((-100, 10), (100, 20));
+        assert_eq!(
+            region.intersection(&left),
+            Some(Bound2::new((-100, 10), (0, 20)))
+        );
+        assert_eq!(
+            region.intersection(&right).map(|b| b.translate((0, 200))),
+            Some(Bound2::new((0, 210), (100, 220)))
+        );
+        assert_eq!(left.intersection(&Bound2::new((0, 0), (10, 10))), None);
+
+        assert!(!region.empty());
+        assert!(Bound2::new((5, 5), (5, 9)).empty());
+        assert!(Bound2::default().empty());
+    }
+}
@@ -1,113 +1,1727 @@
+use super::apng::ApngWriter;
 use super::canvas::Bound2;
+use super::capture_backend::{self, CaptureBackend, CaptureTarget, WindowSelector};
+use super::config::{AnimationFormat, CaptureConfig, GifConfig, LoopCount, RecordingSettings};
+use super::cursor;
+use super::error::{CaptureError, CaptureResult};
+use super::frame_buffer::FramePool;
+use super::frame_delta::ShownFrame;
+use super::palette::{self, Palette, PixelSampler, Quantizer};
+use super::partial_file::PartialFile;
+use super::progress::{RecordingCounters, RecordingProgress};
+use super::replay::ReplayBuffer;
+use super::timelapse::TimelapseStore;
+use super::video::{self, VideoWriter};
+use super::webp::{self, WebpWriter};
 use image::{
-    codecs::gif::GifEncoder as Encoder, DynamicImage::ImageRgba8, GenericImage, GenericImageView,
-    ImageBuffer, RgbImage, Rgba, RgbaImage,
+    DynamicImage::ImageRgba8, GenericImage, GenericImageView, ImageBuffer, RgbImage, Rgba,
+    RgbaImage,
 };
 
-use crate::platform::windows::capture_impl::CaptureImplWin;
-
+use std::borrow::Cow;
+use std::cell::RefCell;
 use std::future::Future;
-use std::sync::mpsc::RecvError;
+use std::io::{Seek, SeekFrom, Write};
+use std::path::PathBuf;
+use std::rc::Rc;
 use std::sync::Arc;
 use std::sync::Mutex;
-// TODO:: remove platform-specified mods
-
-use windows::{
-    core::*,
-    Data::Xml::Dom::*,
-    Win32::System::{DataExchange::*, Threading::*},
-    Win32::{Foundation::*, Graphics::Gdi::*, System::Threading::*, UI::WindowsAndMessaging::*},
-};
+use std::time::{Duration, Instant};
 
 pub struct CaptureDevice {
     pub runtime: tokio::runtime::Runtime,
     pub stop_signal: Arc<Mutex<bool>>,
+    /// Holds the GIF recording without ending it
+    pub pause_signal: Arc<Mutex<bool>>,
+    /// Ends the GIF recording without saving it
+    pub cancel_signal: Arc<Mutex<bool>>,
+    /// Set while a GIF recording runs, until it is encoded
+    pub recording_signal: Arc<Mutex<bool>>,
+    pub backend: Arc<Mutex<Box<dyn CaptureBackend>>>,
+    pub config: CaptureConfig,
+    pub frame_pool: FramePool,
+    pub replay: Arc<Mutex<ReplayBuffer>>,
+    pub replay_stop_signal: Arc<Mutex<bool>>,
+    /// Ends the running timelapse, apart from the other captures
+    pub timelapse_stop_signal: Arc<Mutex<bool>>,
+    /// Set while a timelapse runs, until it is assembled
+    pub timelapse_signal: Arc<Mutex<bool>>,
 }
 
 impl CaptureDevice {
-    pub fn new() -> std::io::Result<Self> {
+    ///
+    /// Creates the device with the backend selected in `config`
+    pub fn new(config: CaptureConfig) -> CaptureResult<Self> {
+        let backend = capture_backend::create_capture_backend(&config.backend)?;
+        Ok(Self::with_backend(config, backend)?)
+    }
+
+    pub fn with_backend(
+        config: CaptureConfig,
+        backend: Box<dyn CaptureBackend>,
+    ) -> std::io::Result<Self> {
         Ok(CaptureDevice {
             runtime: tokio::runtime::Runtime::new()?,
             stop_signal: Arc::new(Mutex::new(false)),
+            pause_signal: Arc::new(Mutex::new(false)),
+            cancel_signal: Arc::new(Mutex::new(false)),
+            recording_signal: Arc::new(Mutex::new(false)),
+            backend: Arc::new(Mutex::new(backend)),
+            replay: Arc::new(Mutex::new(ReplayBuffer::new(&config.replay))),
+            replay_stop_signal: Arc::new(Mutex::new(false)),
+            timelapse_stop_signal: Arc::new(Mutex::new(false)),
+            timelapse_signal: Arc::new(Mutex::new(false)),
+            config,
+            frame_pool: FramePool::new(),
         })
     }
 
-    pub fn capture_image(&self, rect: Bound2) -> RgbaImage {
-        CaptureImplWin::new(HWND(0), rect).capture_image()
+    ///
+    /// Replaces the backend of screenshots and window lookups
+    #[allow(unused)]
+    pub fn set_backend(&mut self, backend: Box<dyn CaptureBackend>) {
+        *self.backend.lock().unwrap() = backend;
+    }
+
+    ///
+    /// Looks up a window that can be passed as `CaptureTarget::Window`
+    pub fn find_window(&self, selector: &WindowSelector) -> Option<u64> {
+        self.backend.lock().unwrap().find_window(selector)
+    }
+
+    pub fn capture_image(&self, target: CaptureTarget) -> CaptureResult<RgbaImage> {
+        let mut backend = self.backend.lock().unwrap();
+        let draw_cursor = open_backend(&mut **backend, target, &self.config)?;
+        capture_frame(&mut **backend, draw_cursor, &self.config, &self.frame_pool)
     }
 
     pub fn stop_capture(&self) {
         let mut stop = self.stop_signal.lock().unwrap();
         *stop = true;
-        println!("stop_capture");
+        log::debug!("stop_capture");
+    }
+
+    ///
+    /// Ends the running GIF recording and drops what was encoded so far
+    pub fn cancel_capture(&self) {
+        *self.cancel_signal.lock().unwrap() = true;
+        self.stop_capture();
+    }
+
+    ///
+    /// Holds the running GIF recording. The time until `resume_capture` is left
+    /// out of the recording. Returns whether a recording was running.
+    pub fn pause_capture(&self) -> bool {
+        let recording = *self.recording_signal.lock().unwrap();
+        if recording {
+            *self.pause_signal.lock().unwrap() = true;
+        }
+        recording
+    }
+
+    pub fn resume_capture(&self) -> bool {
+        *self.pause_signal.lock().unwrap() = false;
+        *self.recording_signal.lock().unwrap()
+    }
+
+    ///
+    /// Starts recording `target` into the replay buffer until `stop_replay`. The
+    /// replay runs on a backend of its own, so it does not hold up the captures
+    /// requested in the meantime.
+    pub fn start_replay(&self, target: CaptureTarget) -> CaptureResult<()> {
+        let fps = self.config.replay.fps.clamp(1, 60);
+        let interval = Duration::from_secs_f64(1.0 / fps as f64);
+        let mut backend = capture_backend::create_capture_backend(&self.config.backend)?;
+        let draw_cursor = open_backend(&mut *backend, target, &self.config)?;
+        *self.replay_stop_signal.lock().unwrap() = false;
+        self.replay.lock().unwrap().clear();
+        let stop_signal = self.replay_stop_signal.clone();
+        let replay = self.replay.clone();
+        let config = self.config.clone();
+        let pool = self.frame_pool.clone();
+        self.runtime.spawn_blocking(move || {
+            let mut scheduler = FrameScheduler::new(interval);
+            while let Some(timestamp) = scheduler.wait(Duration::MAX) {
+                if *stop_signal.lock().unwrap() {
+                    return;
+                }
+                let image = match capture_frame(&mut *backend, draw_cursor, &config, &pool) {
+                    Ok(image) => image,
+                    Err(e) => {
+                        log::error!("instant replay stopped: {}", e);
+                        return;
+                    }
+                };
+                replay
+                    .lock()
+                    .unwrap()
+                    .push(TimedFrame { image, timestamp }, &pool);
+            }
+        });
+        Ok(())
+    }
+
+    pub fn stop_replay(&self) {
+        *self.replay_stop_signal.lock().unwrap() = true;
     }
 
+    ///
+    /// Encodes what the replay buffer holds into an animation of
+    /// `config.animation_format` at `dest`, while the replay keeps recording.
+    pub fn save_replay(
+        &self,
+        dest: PathBuf,
+        finished_cb: Box<dyn FnOnce(CaptureResult<PathBuf>) + Send + 'static>,
+    ) -> CaptureResult<()> {
+        let frames = self.replay.lock().unwrap().frames();
+        if frames.is_empty() {
+            return Err(CaptureError::NoFrames);
+        }
+        check_output_config(&self.config, self.config.animation_format)?;
+        let mut file = PartialFile::create(&dest)?;
+        let interval = Duration::from_secs_f64(1.0 / self.config.replay.fps.clamp(1, 60) as f64);
+        let config = self.config.clone();
+        let pool = self.frame_pool.clone();
+        self.runtime.spawn_blocking(move || {
+            let counters = RecordingCounters::new();
+            let format = config.animation_format;
+            finished_cb(
+                encode_animation(
+                    &mut file, format, frames, interval, &config, &pool, &counters,
+                )
+                .and_then(|_| file.persist()),
+            );
+        });
+        Ok(())
+    }
+
+    ///
+    /// Records `target` into an animation of `format` at `dest` until
+    /// `stop_capture` or the limits of `settings`. The frames are
+    /// encoded into a temporary file next to `dest` while the recording goes
+    /// on, which replaces `dest` once the animation is complete and is removed
+    /// if the recording fails or is cancelled. The recording runs on a backend
+    /// of its own, so screenshots and window lookups are not held up by it.
+    /// Another recording is refused until this one is encoded.
     pub fn capture_gif_async(
         &self,
-        rect: Bound2,
-        fps: u32,
-        duration: f64,
-        finished_cb: Box<dyn FnOnce(Vec<u8>) + Send + 'static>,
-    ) {
-        println!("????");
-        if fps <= 0 || fps > 60 {
-            println!("Wrong fps: {}, it should be in range (0, 60]", fps);
-        }
-        use std::time::Instant;
-        use tokio::time::Interval;
-        let interval = 1.0 / fps as f64;
-        let dur = std::time::Duration::from_secs_f64(duration);
-        let width = rect.get_width();
-        let height = rect.get_height();
+        target: CaptureTarget,
+        settings: RecordingSettings,
+        format: AnimationFormat,
+        dest: PathBuf,
+        mut progress_cb: Box<dyn FnMut(RecordingProgress) + Send + 'static>,
+        finished_cb: Box<dyn FnOnce(CaptureResult<PathBuf>) + Send + 'static>,
+    ) -> CaptureResult<()> {
+        if *self.recording_signal.lock().unwrap() {
+            return Err(CaptureError::Busy("recording"));
+        }
+        check_recording_settings(&settings)?;
+        // the encoder loops the animation as this recording asks
+        let mut encoder_config = self.config.clone();
+        encoder_config.recording = settings;
+        check_output_config(&encoder_config, format)?;
+        let interval = Duration::from_secs_f64(1.0 / settings.fps as f64);
+        let dur = settings
+            .max_duration
+            .map_or(Duration::MAX, Duration::from_secs_f64);
+        let mut file = PartialFile::create(&dest)?;
 
-        let (tx, rx) = std::sync::mpsc::channel();
+        // a failure to open the target ends the stream in place of a frame
+        let (tx, rx) = std::sync::mpsc::channel::<CaptureResult<TimedFrame>>();
         *self.stop_signal.lock().unwrap() = false;
+        *self.pause_signal.lock().unwrap() = false;
+        *self.cancel_signal.lock().unwrap() = false;
+        *self.recording_signal.lock().unwrap() = true;
+        let cancel_signal = self.cancel_signal.clone();
+        let recording_signal = self.recording_signal.clone();
         let stop_signal_clone = self.stop_signal.clone();
-        self.runtime.spawn(async move {
-            let interval = std::time::Duration::from_secs_f64(interval);
-            let mut cap_impl = CaptureImplWin::new(HWND(0), rect);
-            let total_frames = fps * duration as u32;
-            let mut frames = 0;
-            let mut elapse = Instant::now();
-            let end = elapse + dur;
-
-            while frames < total_frames && elapse < end {
-                let img = async { cap_impl.capture_image() }.await;
-                println!("capture {}", frames);
-                {
-                    let mut stop = stop_signal_clone.lock().unwrap();
-                    if *stop == true {
+        let pause_signal = self.pause_signal.clone();
+        let mut backend = capture_backend::create_capture_backend(&self.config.backend)?;
+        let config = self.config.clone();
+        let pool = self.frame_pool.clone();
+        let encoder_pool = self.frame_pool.clone();
+        let counters = Arc::new(RecordingCounters::new());
+        let encoder_counters = counters.clone();
+        // both loops block, on the scheduler and on the channel
+        self.runtime.spawn_blocking(move || {
+            let draw_cursor = match open_backend(&mut *backend, target, &config) {
+                Ok(draw_cursor) => draw_cursor,
+                Err(e) => {
+                    let _ = tx.send(Err(e));
+                    return;
+                }
+            };
+            let mut scheduler = FrameScheduler::new(interval);
+            let mut reported: Option<Duration> = None;
+            let mut captured = 0;
+            while let Some(timestamp) = scheduler.wait(dur) {
+                counters.add_missed(scheduler.take_missed());
+                if *pause_signal.lock().unwrap() {
+                    let paused_at = Instant::now();
+                    while *pause_signal.lock().unwrap() && !*stop_signal_clone.lock().unwrap() {
+                        std::thread::sleep(Duration::from_millis(10));
+                    }
+                    // the frame after the pause follows on at this tick
+                    scheduler.skip(paused_at.elapsed());
+                }
+                let image = match capture_frame(&mut *backend, draw_cursor, &config, &pool) {
+                    Ok(image) => image,
+                    Err(e) => {
+                        let _ = tx.send(Err(e));
                         return;
                     }
+                };
+                if *stop_signal_clone.lock().unwrap() {
+                    return;
+                }
+                if tx.send(Ok(TimedFrame { image, timestamp })).is_err() {
+                    return;
+                }
+                counters.add_captured();
+                captured += 1;
+                if reported.is_none_or(|at| timestamp >= at + PROGRESS_INTERVAL) {
+                    progress_cb(counters.snapshot(timestamp));
+                    reported = Some(timestamp);
+                }
+                if settings.max_frames.is_some_and(|max| captured >= max) {
+                    return;
                 }
-                tx.send(img);
-                elapse += interval;
-                frames += 1;
-                std::thread::sleep(interval);
             }
-            //finished_cb(frames_data);
         });
 
-        self.runtime.spawn(async move {
-            let mut encode_buf = vec![0u8; 0];
-            let mut encoder = Encoder::new(&mut encode_buf);
-            let mut f = 0;
-            loop {
-                f += 1;
-                match rx.recv() {
-                    Ok(img) => {
-                        let mut frame = image::Frame::new(img);
-                        encoder.encode_frame(frame).unwrap();
-                        println!("encoding {}", f);
-                    }
-                    Err(RecvError) => {
-                        println!("encode finished");
-                        break;
+        self.runtime.spawn_blocking(move || {
+            let cancelled = || *cancel_signal.lock().unwrap();
+            let mut error = None;
+            let frames = rx
+                .iter()
+                .take_while(|_| !cancelled())
+                .map_while(|frame| frame.map_err(|e| error = Some(e)).ok());
+            let encoded = encode_animation(
+                &mut file,
+                format,
+                frames,
+                interval,
+                &encoder_config,
+                &encoder_pool,
+                &encoder_counters,
+            );
+            let encoded = match error {
+                Some(e) => Err(e),
+                None if cancelled() => Err(CaptureError::Cancelled),
+                None => encoded,
+            };
+            *recording_signal.lock().unwrap() = false;
+            // a failed recording drops the file, which removes what was written
+            finished_cb(encoded.and_then(|_| file.persist()));
+        });
+        Ok(())
+    }
+
+    ///
+    /// Takes a frame of `target` every `interval` seconds until `stop_timelapse`,
+    /// then assembles the frames into an animation of `config.animation_format`
+    /// at `dest` played back at `playback_fps`. The
+    /// frames wait on disk meanwhile, and the timelapse has a backend of its own
+    /// so it does not hold up other captures for hours. Only one timelapse runs
+    /// at a time.
+    pub fn start_timelapse(
+        &self,
+        target: CaptureTarget,
+        interval: f64,
+        playback_fps: u32,
+        dest: PathBuf,
+        finished_cb: Box<dyn FnOnce(CaptureResult<PathBuf>) + Send + 'static>,
+    ) -> CaptureResult<()> {
+        if *self.timelapse_signal.lock().unwrap() {
+            return Err(CaptureError::Busy("timelapse"));
+        }
+        if !(interval > 0.0 && interval.is_finite()) {
+            return Err(CaptureError::InvalidParameter(format!(
+                "timelapse interval {}s is not positive",
+                interval
+            )));
+        }
+        if playback_fps == 0 || playback_fps > 60 {
+            return Err(CaptureError::InvalidParameter(format!(
+                "playback fps {} is not in range (0, 60]",
+                playback_fps
+            )));
+        }
+        check_output_config(&self.config, self.config.animation_format)?;
+        let interval = Duration::from_secs_f64(interval);
+        let frame_dir = self
+            .config
+            .timelapse
+            .frame_dir
+            .clone()
+            .unwrap_or_else(std::env::temp_dir);
+        let mut store = TimelapseStore::create(&frame_dir)?;
+        let mut backend = capture_backend::create_capture_backend(&self.config.backend)?;
+        let draw_cursor = open_backend(&mut *backend, target, &self.config)?;
+
+        *self.timelapse_stop_signal.lock().unwrap() = false;
+        *self.timelapse_signal.lock().unwrap() = true;
+        let stop_signal = self.timelapse_stop_signal.clone();
+        let timelapse_signal = self.timelapse_signal.clone();
+        let config = self.config.clone();
+        let pool = self.frame_pool.clone();
+        self.runtime.spawn_blocking(move || {
+            let stopped = || *stop_signal.lock().unwrap();
+            let start = Instant::now();
+            let mut tick = 0;
+            'capture: loop {
+                // sleep in short steps to notice a stop within the interval
+                let due = interval * tick;
+                while let Some(left) = due.checked_sub(start.elapsed()) {
+                    if stopped() {
+                        break 'capture;
                     }
+                    std::thread::sleep(left.min(Duration::from_millis(100)));
                 }
+                if stopped() {
+                    break;
+                }
+                let stored =
+                    capture_frame(&mut *backend, draw_cursor, &config, &pool).and_then(|image| {
+                        let stored = store.push(&image);
+                        pool.recycle(image.into_raw());
+                        stored
+                    });
+                if let Err(e) = stored {
+                    *timelapse_signal.lock().unwrap() = false;
+                    finished_cb(Err(e));
+                    return;
+                }
+                tick = next_tick(start.elapsed(), interval, tick + 1);
             }
-            drop(encoder);
-            finished_cb(encode_buf);
+            let assembled = PartialFile::create(&dest).and_then(|mut file| {
+                let format = config.animation_format;
+                store.encode(&mut file, format, playback_fps, &config, &pool)?;
+                file.persist()
+            });
+            *timelapse_signal.lock().unwrap() = false;
+            finished_cb(assembled);
         });
+        Ok(())
+    }
+
+    pub fn stop_timelapse(&self) {
+        *self.timelapse_stop_signal.lock().unwrap() = true;
+    }
+}
+
+///
+/// Encoder of an animation, taking each frame with the time it is shown
+pub trait FrameWriter {
+    fn write_frame(&mut self, image: RgbaImage, delay: Duration) -> CaptureResult<()>;
+
+    /// Completes the file after the last frame
+    fn finish(self) -> CaptureResult<()>;
+}
+
+///
+/// Encodes `frames` into an animation of `format` written to `out`, returning
+/// their buffers to `pool`. The frames and bytes written are counted into
+/// `counters`.
+pub fn encode_animation<W: Write + Seek, I: IntoIterator<Item = TimedFrame>>(
+    out: W,
+    format: AnimationFormat,
+    frames: I,
+    interval: Duration,
+    config: &CaptureConfig,
+    pool: &FramePool,
+    counters: &RecordingCounters,
+) -> CaptureResult<()> {
+    match format {
+        AnimationFormat::Gif => encode_gif(out, frames, interval, config, pool, counters),
+        AnimationFormat::Apng => {
+            let writer = CountingWriter {
+                inner: out,
+                counters,
+            };
+            let encoder = ApngWriter::new(
+                writer,
+                config.recording.loop_count.plays() as u32,
+                config.apng.optimize_frames,
+                pool.clone(),
+            );
+            encode_frames(encoder, frames, interval, config, pool, counters)
+        }
+        AnimationFormat::Webp => {
+            let writer = CountingWriter {
+                inner: out,
+                counters,
+            };
+            let encoder = WebpWriter::new(
+                writer,
+                &config.webp,
+                config.recording.loop_count.plays(),
+                pool.clone(),
+            )?;
+            encode_frames(encoder, frames, interval, config, pool, counters)
+        }
+        AnimationFormat::Mp4 | AnimationFormat::Webm => {
+            let writer = CountingWriter {
+                inner: out,
+                counters,
+            };
+            let encoder = VideoWriter::new(writer, format, &config.video, pool.clone())?;
+            encode_frames(encoder, frames, interval, config, pool, counters)
+        }
+    }
+}
+
+///
+/// Encodes `frames` into a GIF written to `out`, see `encode_animation`
+pub fn encode_gif<W: Write, I: IntoIterator<Item = TimedFrame>>(
+    out: W,
+    frames: I,
+    interval: Duration,
+    config: &CaptureConfig,
+    pool: &FramePool,
+    counters: &RecordingCounters,
+) -> CaptureResult<()> {
+    let writer = CountingWriter {
+        inner: out,
+        counters,
+    };
+    let encoder = GifWriter::new(
+        writer,
+        config.gif.clone(),
+        config.recording.loop_count,
+        pool.clone(),
+    );
+    encode_frames(encoder, frames, interval, config, pool, counters)
+}
+
+///
+/// Hands `frames` to `encoder`. A frame is shown until the next one was
+/// captured, so it is only encoded once the next timestamp is known, and the
+/// last one is shown for `interval`. Unchanged frames are dropped, which leaves
+/// the previous one up until the next change.
+fn encode_frames<F: FrameWriter, I: IntoIterator<Item = TimedFrame>>(
+    mut encoder: F,
+    frames: I,
+    interval: Duration,
+    config: &CaptureConfig,
+    pool: &FramePool,
+    counters: &RecordingCounters,
+) -> CaptureResult<()> {
+    let mut pending: Option<TimedFrame> = None;
+    let mut end = Duration::ZERO;
+    for frame in frames {
+        end = frame.timestamp + interval;
+        if let Some(prev) = pending.as_ref() {
+            if config.skip_unchanged_frames
+                && is_unchanged(&prev.image, &frame.image, config.unchanged_frame_threshold)
+            {
+                pool.recycle(frame.image.into_raw());
+                counters.add_merged();
+                continue;
+            }
+        }
+        if let Some(prev) = pending.take() {
+            let delay = frame.timestamp.saturating_sub(prev.timestamp);
+            encoder.write_frame(prev.image, delay)?;
+            counters.add_encoded();
+        }
+        pending = Some(frame);
+    }
+    match pending {
+        Some(last) => {
+            let delay = end.saturating_sub(last.timestamp);
+            encoder.write_frame(last.image, delay)?;
+            counters.add_encoded();
+        }
+        None => return Err(CaptureError::NoFrames),
+    }
+    encoder.finish()?;
+    Ok(())
+}
+
+/// How often a recording reports its progress
+const PROGRESS_INTERVAL: Duration = Duration::from_millis(250);
+
+///
+/// Passes writes through, counting the bytes
+struct CountingWriter<'a, W: Write> {
+    inner: W,
+    counters: &'a RecordingCounters,
+}
+
+impl<'a, W: Write> Write for CountingWriter<'a, W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        let n = self.inner.write(buf)?;
+        self.counters.add_bytes(n);
+        Ok(n)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.inner.flush()
+    }
+}
+
+impl<'a, W: Write + Seek> Seek for CountingWriter<'a, W> {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.inner.seek(pos)
+    }
+}
+
+/// A captured frame and when it was taken, relative to the start of the capture
+pub struct TimedFrame {
+    pub image: RgbaImage,
+    pub timestamp: Duration,
+}
+
+///
+/// Writes frames into a GIF and hands their buffers back to the pool. The GIF
+/// header needs the frame size, and the global palette if there is one, so the
+/// encoder is created with the first frame written. For a global palette the
+/// frames are held back on disk, and written once it is made from all of them.
+///
+/// Optimized frames after the first are cut to the part that changed, drawn
+/// over the previous frames with the pixels that did not change transparent.
+/// The palettes leave out their last index for that.
+struct GifWriter<W: Write> {
+    /// The writer, shared with the encoder so `finish` can take it back
+    writer: Rc<RefCell<Option<W>>>,
+    encoder: Option<gif::Encoder<SharedWriter<W>>>,
+    config: GifConfig,
+    loop_count: LoopCount,
+    /// The palette shared by all frames, once it was made
+    global: Option<Palette>,
+    /// Frames waiting for the global palette, with their delays, and the
+    /// pixels it is made from
+    held: Option<TimelapseStore>,
+    held_delays: Vec<Duration>,
+    sampler: PixelSampler,
+    /// What the frames written so far show, when optimizing frames
+    shown: Option<ShownFrame>,
+    /// How long the frames written so far are shown, exactly and in the 10ms
+    /// units of GIF delays
+    played: Duration,
+    played_units: u64,
+    pool: FramePool,
+}
+
+impl<W: Write> GifWriter<W> {
+    fn new(writer: W, config: GifConfig, loop_count: LoopCount, pool: FramePool) -> Self {
+        GifWriter {
+            writer: Rc::new(RefCell::new(Some(writer))),
+            encoder: None,
+            config,
+            loop_count,
+            global: None,
+            held: None,
+            held_delays: Vec::new(),
+            sampler: PixelSampler::new(),
+            shown: None,
+            played: Duration::ZERO,
+            played_units: 0,
+            pool,
+        }
+    }
+
+    ///
+    /// The delay of the next frame in 10ms units. Each frame ends at its end
+    /// time rounded to a unit, so the rounding does not add up over the frames.
+    fn delay_units(&mut self, delay: Duration) -> u16 {
+        self.played += delay;
+        let end = ((self.played.as_micros() + 5_000) / 10_000) as u64;
+        let units = (end - self.played_units).min(u16::MAX as u64);
+        self.played_units += units;
+        units as u16
+    }
+
+    fn hold(&mut self, image: RgbaImage, delay: Duration) -> CaptureResult<()> {
+        if self.held.is_none() {
+            self.held = Some(TimelapseStore::create(&std::env::temp_dir())?);
+        }
+        self.held.as_mut().unwrap().push(&image)?;
+        self.held_delays.push(delay);
+        self.sampler.add(&image);
+        self.pool.recycle(image.into_raw());
+        Ok(())
+    }
+
+    ///
+    /// Makes the global palette from the pixels of all frames, then writes them
+    fn write_held(&mut self) -> CaptureResult<()> {
+        let held = match self.held.take() {
+            Some(held) => held,
+            None => return Ok(()),
+        };
+        let samples = std::mem::take(&mut self.sampler).into_samples();
+        self.global = Some(Palette::median_cut(samples, self.palette_size()));
+        let delays = std::mem::take(&mut self.held_delays);
+        for (image, delay) in held.frames().zip(delays) {
+            self.encode(image?, delay)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Colours a palette may have, with room for the transparent index
+    fn palette_size(&self) -> usize {
+        match self.config.optimize_frames {
+            true => self.config.max_colors.min(255) as usize,
+            false => self.config.max_colors as usize,
+        }
+    }
+
+    ///
+    /// The colour table of `palette`, with the transparent colour after it
+    fn color_table(&self, palette: &Palette) -> Vec<u8> {
+        let mut table = palette.to_rgb();
+        if self.config.optimize_frames {
+            table.extend_from_slice(&[0, 0, 0]);
+        }
+        table
+    }
+
+    fn encode(&mut self, image: RgbaImage, delay: Duration) -> Result<(), gif::EncodingError> {
+        let (width, height) = (image.width() as u16, image.height() as u16);
+        let delay = self.delay_units(delay);
+        let max_colors = self.palette_size();
+        let mut local = match self.config.quantizer {
+            Quantizer::NeuQuant { sample_factor } => {
+                Some(Palette::neuquant(image.as_raw(), sample_factor, max_colors))
+            }
+            Quantizer::MedianCut => Some(Palette::median_cut(
+                palette::sample_pixels([&image]),
+                max_colors,
+            )),
+            Quantizer::GlobalPalette => None,
+        };
+        if self.encoder.is_none() {
+            let global = self.global.as_ref().map(|p| self.color_table(p));
+            let writer = SharedWriter(self.writer.clone());
+            let mut encoder =
+                gif::Encoder::new(writer, width, height, &global.unwrap_or_default())?;
+            // a GIF without a repeat count plays once, the count is of the
+            // plays after the first
+            match self.loop_count {
+                LoopCount::Infinite => encoder.set_repeat(gif::Repeat::Infinite)?,
+                LoopCount::Times(n) if n > 1 => encoder.set_repeat(gif::Repeat::Finite(n - 1))?,
+                LoopCount::Once | LoopCount::Times(_) => {}
+            }
+            self.encoder = Some(encoder);
+        }
+        let local_table = local.as_ref().map(|p| self.color_table(p));
+        let palette = local.as_mut().or(self.global.as_mut()).unwrap();
+        let indices = palette.index_image(&image, self.config.dithering);
+        let mut frame = gif::Frame {
+            width,
+            height,
+            delay,
+            palette: local_table,
+            ..gif::Frame::default()
+        };
+        let size = (width as usize, height as usize);
+        match self.shown.as_mut() {
+            Some(shown) if shown.dimensions() == size => {
+                let transparent = palette.len() as u8;
+                let delta = shown.apply(&indices, palette, transparent);
+                frame.left = delta.left;
+                frame.top = delta.top;
+                frame.width = delta.width;
+                frame.height = delta.height;
+                frame.transparent = Some(transparent);
+                frame.dispose = gif::DisposalMethod::Keep;
+                frame.buffer = Cow::Owned(delta.indices);
+            }
+            _ => {
+                if self.config.optimize_frames {
+                    frame.dispose = gif::DisposalMethod::Keep;
+                    self.shown = Some(ShownFrame::new(size.0, size.1, &indices, palette));
+                }
+                frame.buffer = Cow::Owned(indices);
+            }
+        }
+        self.encoder.as_mut().unwrap().write_frame(&frame)?;
+        self.pool.recycle(image.into_raw());
+        Ok(())
+    }
+}
+
+impl<W: Write> FrameWriter for GifWriter<W> {
+    fn write_frame(&mut self, image: RgbaImage, delay: Duration) -> CaptureResult<()> {
+        if self.config.quantizer == Quantizer::GlobalPalette {
+            return self.hold(image, delay);
+        }
+        Ok(self.encode(image, delay)?)
+    }
+
+    ///
+    /// Writes the frames still held back and the trailer. The encoder would
+    /// write the trailer when it is dropped, ignoring errors, so the writer is
+    /// taken from it first and the trailer written here.
+    fn finish(mut self) -> CaptureResult<()> {
+        self.write_held()?;
+        let mut writer = self.writer.borrow_mut().take().unwrap();
+        if self.encoder.take().is_some() {
+            writer.write_all(&[GIF_TRAILER])?;
+        }
+        writer.flush()?;
+        Ok(())
+    }
+}
+
+/// The byte that ends a GIF
+const GIF_TRAILER: u8 = 0x3b;
+
+///
+/// The writer of a `gif::Encoder`, dropping the writes once `GifWriter` took
+/// the writer back
+struct SharedWriter<W: Write>(Rc<RefCell<Option<W>>>);
+
+impl<W: Write> Write for SharedWriter<W> {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        match self.0.borrow_mut().as_mut() {
+            Some(writer) => writer.write(buf),
+            None => Ok(buf.len()),
+        }
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        match self.0.borrow_mut().as_mut() {
+            Some(writer) => writer.flush(),
+            None => Ok(()),
+        }
+    }
+}
+
+///
+/// Checks the settings of `format` when a recording starts, rather than failing
+/// on its first frame
+fn check_output_config(config: &CaptureConfig, format: AnimationFormat) -> CaptureResult<()> {
+    if config.recording.loop_count == LoopCount::Times(0) {
+        return Err(CaptureError::InvalidParameter(
+            "loop count 0 plays the animation not at all".to_string(),
+        ));
+    }
+    match format {
+        AnimationFormat::Gif => check_gif_config(&config.gif),
+        AnimationFormat::Apng => Ok(()),
+        AnimationFormat::Webp => {
+            if !(0.0..=100.0).contains(&config.webp.quality) {
+                return Err(CaptureError::InvalidParameter(format!(
+                    "WebP quality {} is not in range [0, 100]",
+                    config.webp.quality
+                )));
+            }
+            webp::Library::load().map(|_| ())
+        }
+        AnimationFormat::Mp4 | AnimationFormat::Webm => {
+            let preset = match format {
+                AnimationFormat::Mp4 => &config.video.mp4,
+                _ => &config.video.webm,
+            };
+            if preset.crf > 63 {
+                return Err(CaptureError::InvalidParameter(format!(
+                    "{} CRF {} is not in range [0, 63]",
+                    preset.codec, preset.crf
+                )));
+            }
+            video::check_ffmpeg(&config.video.ffmpeg)
+        }
+    }
+}
+
+fn check_recording_settings(settings: &RecordingSettings) -> CaptureResult<()> {
+    if settings.fps == 0 || settings.fps > 60 {
+        return Err(CaptureError::InvalidParameter(format!(
+            "fps {} is not in range (0, 60]",
+            settings.fps
+        )));
+    }
+    if let Some(duration) = settings.max_duration {
+        if !(duration > 0.0 && duration.is_finite()) {
+            return Err(CaptureError::InvalidParameter(format!(
+                "duration {}s is not positive",
+                duration
+            )));
+        }
+    }
+    if settings.max_frames == Some(0) {
+        return Err(CaptureError::InvalidParameter(
+            "max frames 0 records nothing".to_string(),
+        ));
+    }
+    Ok(())
+}
+
+fn check_gif_config(config: &GifConfig) -> CaptureResult<()> {
+    if !(2..=256).contains(&config.max_colors) {
+        return Err(CaptureError::InvalidParameter(format!(
+            "max colors {} is not in range [2, 256]",
+            config.max_colors
+        )));
+    }
+    if let Quantizer::NeuQuant { sample_factor } = config.quantizer {
+        if !(1..=30).contains(&sample_factor) {
+            return Err(CaptureError::InvalidParameter(format!(
+                "NeuQuant sample factor {} is not in range [1, 30]",
+                sample_factor
+            )));
+        }
+    }
+    Ok(())
+}
+
+///
+/// Whether `frame` differs from `prev` in at most `threshold` of its pixels
+fn is_unchanged(prev: &RgbaImage, frame: &RgbaImage, threshold: f32) -> bool {
+    if prev.dimensions() != frame.dimensions() {
+        return false;
+    }
+    if threshold <= 0.0 {
+        return prev.as_raw() == frame.as_raw();
+    }
+    let changed = prev
+        .pixels()
+        .zip(frame.pixels())
+        .filter(|(a, b)| a != b)
+        .count();
+    changed as f32 <= threshold * (frame.width() * frame.height()) as f32
+}
+
+///
+/// Paces the capture on ticks at fixed offsets from the start, so slow frames do
+/// not shift the following ones. Ticks that already passed are skipped instead
+/// of being captured late in a burst.
+struct FrameScheduler {
+    start: Instant,
+    interval: Duration,
+    next_tick: u32,
+    missed: u32,
+}
+
+impl FrameScheduler {
+    fn new(interval: Duration) -> Self {
+        FrameScheduler {
+            start: Instant::now(),
+            interval,
+            next_tick: 0,
+            missed: 0,
+        }
+    }
+
+    ///
+    /// Sleeps until the next tick and returns its offset from the start, or
+    /// `None` once `duration` is over
+    fn wait(&mut self, duration: Duration) -> Option<Duration> {
+        let tick = next_tick(self.start.elapsed(), self.interval, self.next_tick);
+        let at = self.interval * tick;
+        if at >= duration {
+            return None;
+        }
+        self.missed += tick - self.next_tick;
+        self.next_tick = tick + 1;
+        if let Some(sleep) = at.checked_sub(self.start.elapsed()) {
+            std::thread::sleep(sleep);
+        }
+        Some(self.start.elapsed().max(at))
+    }
+
+    ///
+    /// Leaves `paused` out of the timeline, as if the capture started that much
+    /// later
+    fn skip(&mut self, paused: Duration) {
+        self.start += paused;
+    }
+
+    ///
+    /// Ticks skipped since the last call, because the capture fell behind
+    fn take_missed(&mut self) -> usize {
+        std::mem::take(&mut self.missed) as usize
+    }
+}
+
+///
+/// The tick to capture next, at least `tick`. A late tick is still taken until
+/// the one after it is due, older ones are skipped.
+fn next_tick(elapsed: Duration, interval: Duration, tick: u32) -> u32 {
+    let current = (elapsed.as_nanos() / interval.as_nanos().max(1)) as u32;
+    tick.max(current)
+}
+
+///
+/// Opens `target` on the backend. Returns whether the cursor still has to be
+/// drawn onto the frames, which is the case when the backend does not do it
+/// itself.
+fn open_backend(
+    backend: &mut dyn CaptureBackend,
+    target: CaptureTarget,
+    config: &CaptureConfig,
+) -> CaptureResult<bool> {
+    match target {
+        CaptureTarget::Region(rect) => {
+            if rect.empty() {
+                return Err(CaptureError::InvalidParameter(format!(
+                    "region {:?} is empty",
+                    rect
+                )));
+            }
+            backend.open(rect)?;
+        }
+        CaptureTarget::Window(window) => backend.open_window(window)?,
+    }
+    let drawn_by_backend = backend.set_draw_cursor(config.capture_cursor);
+    Ok(config.capture_cursor && !drawn_by_backend)
+}
+
+///
+/// Grabs a frame into a buffer from `pool`
+fn capture_frame(
+    backend: &mut dyn CaptureBackend,
+    draw_cursor: bool,
+    config: &CaptureConfig,
+    pool: &FramePool,
+) -> CaptureResult<RgbaImage> {
+    let bound = backend.frame_bound();
+    let mut buf = pool.take();
+    if let Err(e) = backend.grab_frame(&mut buf) {
+        pool.recycle(buf);
+        return Err(e);
+    }
+    let (width, height) = backend.size();
+    let mut img = capture_backend::frame_to_rgba_image(backend.pixel_format(), width, height, buf);
+    if draw_cursor {
+        cursor::draw_backend_cursor(&mut img, bound, backend, config.cursor_scale);
+    }
+    Ok(img)
+}
+
+#[cfg(test)]
+mod capture_device_test {
+    use super::*;
+    use crate::app::capture_backend::CaptureBackendKind;
+    use crate::app::config::ReplayConfig;
+    use crate::app::palette::Dithering;
+    use crate::app::synthetic_capture::{SyntheticCaptureBackend, SyntheticSource};
+    use image::{codecs::gif::GifDecoder, AnimationDecoder, Delay};
+
+    fn synthetic_device(source: SyntheticSource) -> CaptureDevice {
+        let backend = SyntheticCaptureBackend::new(source.clone()).unwrap();
+        // recordings create their backends from the config
+        let config = CaptureConfig {
+            backend: CaptureBackendKind::Synthetic(source),
+            ..CaptureConfig::default()
+        };
+        CaptureDevice::with_backend(config, Box::new(backend)).unwrap()
+    }
+
+    fn recording(fps: u32, seconds: f64) -> RecordingSettings {
+        RecordingSettings {
+            fps,
+            max_duration: Some(seconds),
+            ..RecordingSettings::default()
+        }
+    }
+
+    ///
+    /// A fresh directory to save the captures of a test into
+    fn output_dir(test: &str) -> PathBuf {
+        let dir = std::env::temp_dir().join(format!("polaroid-{}-{}", test, std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        dir
+    }
+
+    ///
+    /// Reads back the file saved by a capture task and removes its directory,
+    /// which must not hold anything else
+    fn read_saved(saved: CaptureResult<PathBuf>) -> Vec<u8> {
+        let path = saved.unwrap();
+        let dir = path.parent().unwrap();
+        assert_eq!(std::fs::read_dir(dir).unwrap().count(), 1);
+        let data = std::fs::read(&path).unwrap();
+        std::fs::remove_dir_all(dir).unwrap();
+        data
+    }
+
+    #[test]
+    fn capture_gif_from_synthetic_backend_test() {
+        let device = synthetic_device(SyntheticSource::FrameCounter);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let (progress_tx, progress_rx) = std::sync::mpsc::channel();
+        device
+            .capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                recording(20, 1.0),
+                AnimationFormat::Gif,
+                output_dir("gif").join("CAP.gif"),
+                Box::new(move |progress| progress_tx.send(progress).unwrap()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        let data = read_saved(rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap());
+
+        // reported a few times a second, not on every frame
+        let progress: Vec<RecordingProgress> = progress_rx.try_iter().collect();
+        assert!((2..10).contains(&progress.len()), "{:?}", progress);
+        assert!(progress
+            .windows(2)
+            .all(|p| p[0].frames_captured < p[1].frames_captured && p[0].elapsed < p[1].elapsed));
+
+        let frames = GifDecoder::new(&data[..])
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 20);
+        assert_eq!(frames[0].buffer().dimensions(), (40, 24));
+        assert!(frames
+            .iter()
+            .all(|f| f.delay() > Delay::from_numer_denom_ms(0, 1)));
+    }
+
+    #[test]
+    fn max_frames_and_loop_count_test() {
+        let device = synthetic_device(SyntheticSource::FrameCounter);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let settings = RecordingSettings {
+            fps: 20,
+            max_duration: None,
+            max_frames: Some(6),
+            loop_count: LoopCount::Times(3),
+        };
+        device
+            .capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                settings,
+                AnimationFormat::Gif,
+                output_dir("limits").join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        let data = read_saved(rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap());
+
+        let frames = GifDecoder::new(&data[..])
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 6);
+        // three plays are the first and two repeats
+        let netscape = data.windows(11).position(|w| w == b"NETSCAPE2.0").unwrap();
+        assert_eq!(&data[netscape + 11..netscape + 15], &[3, 1, 2, 0]);
+
+        let settings = RecordingSettings {
+            max_frames: Some(0),
+            ..settings
+        };
+        assert!(matches!(
+            device.capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                settings,
+                AnimationFormat::Gif,
+                std::env::temp_dir().join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(|_| ()),
+            ),
+            Err(CaptureError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn static_frames_are_merged_test() {
+        let dir = std::env::temp_dir().join(format!("polaroid-static-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0xff, 0xff]))
+            .save(dir.join("0.png"))
+            .unwrap();
+        let device = synthetic_device(SyntheticSource::Directory(dir.clone()));
+        let (tx, rx) = std::sync::mpsc::channel();
+        device
+            .capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (16, 16))),
+                recording(20, 0.5),
+                AnimationFormat::Gif,
+                output_dir("static-out").join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        let data = read_saved(rx.recv_timeout(std::time::Duration::from_secs(10)).unwrap());
+        std::fs::remove_dir_all(&dir).unwrap();
+
+        let frames = GifDecoder::new(&data[..])
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        assert_eq!(frames.len(), 1);
+        let (numer, denom) = frames[0].delay().numer_denom_ms();
+        assert!(numer / denom >= 450);
+    }
+
+    #[test]
+    fn save_replay_keeps_last_seconds_test() {
+        let config = CaptureConfig {
+            backend: CaptureBackendKind::Synthetic(SyntheticSource::FrameCounter),
+            replay: ReplayConfig {
+                fps: 20,
+                seconds: 0.5,
+                ..ReplayConfig::default()
+            },
+            ..CaptureConfig::default()
+        };
+        let device = CaptureDevice::new(config).unwrap();
+        device
+            .start_replay(CaptureTarget::Region(Bound2::new((0, 0), (40, 24))))
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(1200));
+
+        let (tx, rx) = std::sync::mpsc::channel();
+        device
+            .save_replay(
+                output_dir("replay").join("REPLAY.gif"),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        device.stop_replay();
+        let data = read_saved(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+
+        let frames = GifDecoder::new(&data[..])
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        // 0.5s at 20fps, give or take the frames around the edges
+        assert!((9..=12).contains(&frames.len()), "{} frames", frames.len());
+    }
+
+    #[test]
+    fn paused_time_is_left_out_test() {
+        let device = synthetic_device(SyntheticSource::FrameCounter);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let start = Instant::now();
+        device
+            .capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                recording(20, 1.0),
+                AnimationFormat::Gif,
+                output_dir("paused").join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(device.pause_capture());
+        std::thread::sleep(Duration::from_millis(500));
+        assert!(device.resume_capture());
+        let data = read_saved(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+        assert!(!device.pause_capture());
+        assert!(start.elapsed() >= Duration::from_millis(1400));
+
+        let frames = GifDecoder::new(&data[..])
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        let played: u32 = frames
+            .iter()
+            .map(|f| {
+                let (numer, denom) = f.delay().numer_denom_ms();
+                numer / denom
+            })
+            .sum();
+        assert!((900..=1100).contains(&played), "played {}ms", played);
+    }
+
+    #[test]
+    fn timelapse_plays_back_at_playback_fps_test() {
+        let config = CaptureConfig {
+            backend: CaptureBackendKind::Synthetic(SyntheticSource::FrameCounter),
+            ..CaptureConfig::default()
+        };
+        let device = CaptureDevice::new(config).unwrap();
+        let (tx, rx) = std::sync::mpsc::channel();
+        device
+            .start_timelapse(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                0.1,
+                25,
+                output_dir("timelapse").join("TIMELAPSE.gif"),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(250));
+        assert!(matches!(
+            device.start_timelapse(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                0.1,
+                25,
+                output_dir("timelapse-second").join("TIMELAPSE.gif"),
+                Box::new(|_| ()),
+            ),
+            Err(CaptureError::Busy(_))
+        ));
+        std::fs::remove_dir_all(output_dir("timelapse-second")).unwrap();
+        // ending a recording or closing the selector leaves the timelapse running
+        device.stop_capture();
+        std::thread::sleep(Duration::from_millis(300));
+        assert!(rx.try_recv().is_err());
+        device.stop_timelapse();
+        let data = read_saved(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+
+        let frames = GifDecoder::new(&data[..])
+            .unwrap()
+            .into_frames()
+            .collect_frames()
+            .unwrap();
+        // a frame at 0, 100, ..., 500ms
+        assert!((5..=7).contains(&frames.len()), "{} frames", frames.len());
+        assert!(frames
+            .iter()
+            .all(|f| f.delay() == Delay::from_numer_denom_ms(40, 1)));
+    }
+
+    #[test]
+    fn capture_errors_test() {
+        let device = synthetic_device(SyntheticSource::FrameCounter);
+        let region = CaptureTarget::Region(Bound2::new((0, 0), (40, 24)));
+        let dir = output_dir("errors");
+        let dest = dir.join("CAP.gif");
+        let ignore = || Box::new(|_| ());
+        assert!(matches!(
+            device.capture_gif_async(
+                region,
+                recording(0, 1.0),
+                AnimationFormat::Gif,
+                dest.clone(),
+                Box::new(|_| ()),
+                ignore()
+            ),
+            Err(CaptureError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            device.capture_image(CaptureTarget::Region(Bound2::new((5, 5), (5, 20)))),
+            Err(CaptureError::InvalidParameter(_))
+        ));
+        assert!(matches!(
+            device.save_replay(dest.clone(), ignore()),
+            Err(CaptureError::NoFrames)
+        ));
+
+        // the synthetic backend has no windows, which is only found out by the
+        // capture task
+        let (tx, rx) = std::sync::mpsc::channel();
+        device
+            .capture_gif_async(
+                CaptureTarget::Window(1),
+                recording(20, 1.0),
+                AnimationFormat::Gif,
+                dest,
+                Box::new(|_| ()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+            Err(CaptureError::Backend(_))
+        ));
+        // nothing is left of the failed capture
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A frame the backend cannot read fails the recording instead of being
+    /// saved blank
+    #[test]
+    fn failed_frame_fails_recording_test() {
+        let source_dir = output_dir("failed-frame-source");
+        RgbaImage::from_pixel(40, 24, image::Rgba([0, 0, 0xff, 0xff]))
+            .save(source_dir.join("0.png"))
+            .unwrap();
+        let device = synthetic_device(SyntheticSource::Directory(source_dir.clone()));
+        let dir = output_dir("failed-frame");
+        let (tx, rx) = std::sync::mpsc::channel();
+        device
+            .capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                recording(20, 2.0),
+                AnimationFormat::Gif,
+                dir.join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        std::fs::remove_dir_all(&source_dir).unwrap();
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+            Err(CaptureError::Backend(_))
+        ));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn capture_image_while_recording_test() {
+        let device = synthetic_device(SyntheticSource::FrameCounter);
+        let (tx, rx) = std::sync::mpsc::channel();
+        let settings = RecordingSettings {
+            max_duration: None,
+            ..recording(20, 1.0)
+        };
+        device
+            .capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                settings,
+                AnimationFormat::Gif,
+                output_dir("while-recording").join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(200));
+        // the recording runs on a backend of its own
+        let started = Instant::now();
+        let image = device
+            .capture_image(CaptureTarget::Region(Bound2::new((0, 0), (16, 16))))
+            .unwrap();
+        assert_eq!(image.dimensions(), (16, 16));
+        assert!(started.elapsed() < Duration::from_secs(1));
+        assert!(matches!(
+            device.capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                settings,
+                AnimationFormat::Gif,
+                output_dir("while-recording").join("CAP2.gif"),
+                Box::new(|_| ()),
+                Box::new(|_| ()),
+            ),
+            Err(CaptureError::Busy(_))
+        ));
+        device.stop_capture();
+        read_saved(rx.recv_timeout(Duration::from_secs(10)).unwrap());
+    }
+
+    #[test]
+    fn cancelled_capture_is_removed_test() {
+        let device = synthetic_device(SyntheticSource::FrameCounter);
+        let dir = output_dir("cancel");
+        let (tx, rx) = std::sync::mpsc::channel();
+        device
+            .capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                recording(20, 5.0),
+                AnimationFormat::Gif,
+                dir.join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(move |saved| tx.send(saved).unwrap()),
+            )
+            .unwrap();
+        std::thread::sleep(Duration::from_millis(300));
+        // frames were written to the temporary file meanwhile
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+        device.cancel_capture();
+        assert!(matches!(
+            rx.recv_timeout(Duration::from_secs(10)).unwrap(),
+            Err(CaptureError::Cancelled)
+        ));
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    #[test]
+    fn gif_delays_keep_total_duration_test() {
+        // 15 fps, 66.7ms a frame, which no number of 10ms units matches
+        let interval = Duration::from_secs_f64(1.0 / 15.0);
+        let frames = (0..30u32).map(|i| TimedFrame {
+            image: RgbaImage::from_pixel(8, 8, Rgba([(i * 8) as u8, 0, 0, 0xff])),
+            timestamp: interval * i,
+        });
+        let mut data = Vec::new();
+        encode_gif(
+            &mut data,
+            frames,
+            interval,
+            &CaptureConfig::default(),
+            &FramePool::new(),
+            &RecordingCounters::new(),
+        )
+        .unwrap();
+
+        let mut decoder = gif::DecodeOptions::new().read_info(&data[..]).unwrap();
+        let mut ends = Vec::new();
+        let mut played = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            played += frame.delay as u32 * 10;
+            ends.push(played);
+        }
+        assert_eq!(ends.len(), 30);
+        // each frame ends within 5ms of its end in the recording
+        for (i, end) in ends.iter().enumerate() {
+            let recorded = (interval * (i as u32 + 1)).as_secs_f64() * 1000.0;
+            assert!((*end as f64 - recorded).abs() <= 5.0, "{} at {}", end, i);
+        }
+        assert_eq!(played, 2000);
+    }
+
+    ///
+    /// Takes `room` bytes, then fails like a full disk
+    struct FullWriter {
+        data: Vec<u8>,
+        room: usize,
+    }
+
+    impl Write for FullWriter {
+        fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+            if self.data.len() + buf.len() > self.room {
+                return Err(std::io::Error::other("no space left"));
+            }
+            self.data.extend_from_slice(buf);
+            Ok(buf.len())
+        }
+
+        fn flush(&mut self) -> std::io::Result<()> {
+            Ok(())
+        }
+    }
+
+    #[test]
+    fn gif_trailer_test() {
+        let encode = |out: &mut FullWriter| {
+            let frames = (0..3u32).map(|i| TimedFrame {
+                image: RgbaImage::from_pixel(8, 8, Rgba([(i * 60) as u8, 0, 0, 0xff])),
+                timestamp: Duration::from_millis(100) * i,
+            });
+            encode_gif(
+                out,
+                frames,
+                Duration::from_millis(100),
+                &CaptureConfig::default(),
+                &FramePool::new(),
+                &RecordingCounters::new(),
+            )
+        };
+        let mut out = FullWriter {
+            data: Vec::new(),
+            room: usize::MAX,
+        };
+        encode(&mut out).unwrap();
+        assert_eq!(out.data.last(), Some(&GIF_TRAILER));
+
+        // no room for the trailer alone fails the GIF
+        let mut out = FullWriter {
+            data: Vec::new(),
+            room: out.data.len() - 1,
+        };
+        assert!(matches!(encode(&mut out), Err(CaptureError::Io(_))));
+    }
+
+    #[test]
+    fn global_palette_test() {
+        let config = CaptureConfig {
+            gif: GifConfig {
+                quantizer: Quantizer::GlobalPalette,
+                dithering: Dithering::FloydSteinberg,
+                max_colors: 16,
+                optimize_frames: false,
+            },
+            ..CaptureConfig::default()
+        };
+        let frames = (0..12u32).map(|i| TimedFrame {
+            image: RgbaImage::from_fn(32, 8, |x, y| {
+                Rgba([(x * 8) as u8, (y * 32) as u8, (i * 20) as u8, 0xff])
+            }),
+            timestamp: Duration::from_millis(100) * i,
+        });
+        let mut data = Vec::new();
+        let pool = FramePool::new();
+        encode_gif(
+            &mut data,
+            frames,
+            Duration::from_millis(100),
+            &config,
+            &pool,
+            &RecordingCounters::new(),
+        )
+        .unwrap();
+
+        let mut options = gif::DecodeOptions::new();
+        options.set_color_output(gif::ColorOutput::Indexed);
+        let mut decoder = options.read_info(&data[..]).unwrap();
+        assert_eq!(decoder.global_palette().unwrap().len(), 16 * 3);
+        let mut count = 0;
+        while let Some(frame) = decoder.read_next_frame().unwrap() {
+            assert!(frame.palette.is_none());
+            count += 1;
+        }
+        // the frames held back for the palette come out as well
+        assert_eq!(count, 12);
+
+        // a colour only the last frames show is in the palette
+        let frames = (0..40u32).map(|i| TimedFrame {
+            image: match i {
+                0..=35 => RgbaImage::from_fn(32, 8, |x, _| Rgba([(x * 8) as u8, 0, 0, 0xff])),
+                _ => RgbaImage::from_pixel(32, 8, Rgba([0, 0, 255, 0xff])),
+            },
+            timestamp: Duration::from_millis(100) * i,
+        });
+        let mut data = Vec::new();
+        encode_gif(
+            &mut data,
+            frames,
+            Duration::from_millis(100),
+            &config,
+            &pool,
+            &RecordingCounters::new(),
+        )
+        .unwrap();
+        let decoder = gif::DecodeOptions::new().read_info(&data[..]).unwrap();
+        let global = decoder.global_palette().unwrap();
+        assert!(global.chunks(3).any(|c| c == [0, 0, 255]));
+
+        let mut device = synthetic_device(SyntheticSource::FrameCounter);
+        device.config.gif.max_colors = 300;
+        assert!(matches!(
+            device.capture_gif_async(
+                CaptureTarget::Region(Bound2::new((0, 0), (40, 24))),
+                recording(20, 1.0),
+                AnimationFormat::Gif,
+                std::env::temp_dir().join("CAP.gif"),
+                Box::new(|_| ()),
+                Box::new(|_| ()),
+            ),
+            Err(CaptureError::InvalidParameter(_))
+        ));
+    }
+
+    #[test]
+    fn optimized_frames_test() {
+        let frames = || {
+            (0..10u32).map(|i| TimedFrame {
+                // a cursor moving over a text-like pattern
+                image: RgbaImage::from_fn(160, 120, |x, y| {
+                    match (x / 4 == i && y < 8, (x * 7 + y * 3) % 11 < 3) {
+                        (true, _) => Rgba([0xff, 0, 0, 0xff]),
+                        (_, true) => Rgba([0x20, 0x20, 0x20, 0xff]),
+                        (_, false) => Rgba([0xf0, 0xf0, 0xf0, 0xff]),
+                    }
+                }),
+                timestamp: Duration::from_millis(100) * i,
+            })
+        };
+        let encode = |optimize_frames| {
+            let config = CaptureConfig {
+                gif: GifConfig {
+                    quantizer: Quantizer::MedianCut,
+                    optimize_frames,
+                    ..GifConfig::default()
+                },
+                ..CaptureConfig::default()
+            };
+            let mut data = Vec::new();
+            let counters = RecordingCounters::new();
+            encode_gif(
+                &mut data,
+                frames(),
+                Duration::from_millis(100),
+                &config,
+                &FramePool::new(),
+                &counters,
+            )
+            .unwrap();
+            data
+        };
+        let decode = |data: &[u8]| {
+            GifDecoder::new(data)
+                .unwrap()
+                .into_frames()
+                .collect_frames()
+                .unwrap()
+        };
+
+        let (full, optimized) = (encode(false), encode(true));
+        assert!(
+            optimized.len() * 4 < full.len(),
+            "{} of {} bytes",
+            optimized.len(),
+            full.len()
+        );
+        // drawn over each other the cut frames show the same as the full ones
+        let expected: Vec<RgbaImage> = frames().map(|f| f.image).collect();
+        for (frame, expected) in decode(&optimized).iter().zip(expected.iter()) {
+            assert_eq!(frame.buffer(), expected);
+        }
+        assert_eq!(decode(&full).len(), decode(&optimized).len());
+    }
+
+    #[test]
+    fn apng_round_trip_test() {
+        use image::codecs::png::PngDecoder;
+        use std::io::Cursor;
+
+        let frames = || {
+            (0..6u32).map(|i| TimedFrame {
+                // a gradient no GIF palette holds, with a bar moving over it
+                image: RgbaImage::from_fn(96, 64, |x, y| match x / 8 == i {
+                    true => Rgba([0xff, 0, 0, 0xff]),
+                    false => Rgba([x as u8 * 2, y as u8 * 3, (x + y) as u8, 0xff]),
+                }),
+                timestamp: Duration::from_millis(50) * i,
+            })
+        };
+        let encode = |optimize_frames| {
+            let mut config = CaptureConfig::default();
+            config.apng.optimize_frames = optimize_frames;
+            let mut data = Cursor::new(Vec::new());
+            let counters = RecordingCounters::new();
+            encode_animation(
+                &mut data,
+                AnimationFormat::Apng,
+                frames(),
+                Duration::from_millis(50),
+                &config,
+                &FramePool::new(),
+                &counters,
+            )
+            .unwrap();
+            data.into_inner()
+        };
+
+        let (full, optimized) = (encode(false), encode(true));
+        assert!(optimized.len() < full.len());
+        let expected: Vec<RgbaImage> = frames().map(|f| f.image).collect();
+        for data in [full, optimized] {
+            let decoded = PngDecoder::new(&data[..])
+                .unwrap()
+                .apng()
+                .into_frames()
+                .collect_frames()
+                .unwrap();
+            assert_eq!(decoded.len(), 6);
+            for (frame, expected) in decoded.iter().zip(expected.iter()) {
+                assert_eq!(frame.buffer(), expected);
+                assert_eq!(frame.delay(), Delay::from_numer_denom_ms(50, 1));
+            }
+        }
+    }
+
+    #[test]
+    fn unchanged_threshold_test() {
+        let prev = RgbaImage::from_pixel(10, 10, Rgba([0, 0, 0, 0xff]));
+        let mut frame = prev.clone();
+        assert!(is_unchanged(&prev, &frame, 0.0));
+        frame.put_pixel(0, 0, Rgba([0xff, 0, 0, 0xff]));
+        assert!(!is_unchanged(&prev, &frame, 0.0));
+        assert!(is_unchanged(&prev, &frame, 0.01));
+        frame.put_pixel(1, 0, Rgba([0xff, 0, 0, 0xff]));
+        assert!(!is_unchanged(&prev, &frame, 0.01));
+    }
+
+    #[test]
+    fn scheduler_skips_missed_ticks_test() {
+        let interval = Duration::from_millis(50);
+        // ahead of the tick, or a bit late
+        assert_eq!(next_tick(Duration::from_millis(20), interval, 1), 1);
+        assert_eq!(next_tick(Duration::from_millis(60), interval, 1), 1);
+        // tick 1 took until 170ms, tick 2 is skipped and 3 is taken late
+        assert_eq!(next_tick(Duration::from_millis(170), interval, 2), 3);
     }
 }
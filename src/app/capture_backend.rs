@@ -0,0 +1,207 @@
+use super::canvas::Bound2;
+use super::error::{CaptureError, CaptureResult};
+use super::frame_buffer;
+use super::synthetic_capture::{SyntheticCaptureBackend, SyntheticSource};
+use crate::platform;
+use image::RgbaImage;
+
+/// Byte order of a single pixel in a frame buffer
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+#[allow(unused)]
+pub enum PixelFormat {
+    Rgba8,
+    Bgra8,
+}
+
+impl PixelFormat {
+    pub fn bytes_per_pixel(&self) -> usize {
+        4
+    }
+}
+
+/// Selects which capture backend the `CaptureDevice` is created with
+#[derive(Debug, Clone, PartialEq, Eq, Default)]
+pub enum CaptureBackendKind {
+    /// The default backend of the running platform
+    #[default]
+    Native,
+    /// XGetImage/MIT-SHM grabbing of the X11 root window
+    X11,
+    /// wlr-screencopy on wlroots based Wayland compositors
+    Wayland,
+    /// Frames from files or generated patterns, for tests
+    #[allow(unused)]
+    Synthetic(SyntheticSource),
+}
+
+///
+/// Creates the backend selected by `kind`, failing rather than handing out a
+/// backend that cannot capture anything
+pub fn create_capture_backend(kind: &CaptureBackendKind) -> CaptureResult<Box<dyn CaptureBackend>> {
+    match kind {
+        CaptureBackendKind::Synthetic(source) => match SyntheticCaptureBackend::new(source.clone())
+        {
+            Ok(backend) => Ok(Box::new(backend)),
+            Err(e) => Err(CaptureError::Backend(format!(
+                "failed to open synthetic source {:?}: {}",
+                source, e
+            ))),
+        },
+        _ => platform::create_capture_backend(kind),
+    }
+}
+
+/// Ways to pick the top level window to capture
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(unused)]
+pub enum WindowSelector {
+    /// Native window id
+    Id(u64),
+    /// Topmost window whose title contains the text, case sensitive
+    Title(String),
+    /// Topmost window under the point, in desktop coordinates
+    At(i32, i32),
+}
+
+/// What a capture records
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum CaptureTarget {
+    Region(Bound2),
+    /// A window found through `CaptureBackend::find_window`
+    Window(u64),
+}
+
+/// The pointer image and where it is on the desktop
+#[derive(Debug, Clone)]
+pub struct CursorImage {
+    pub image: RgbaImage,
+    /// Offset of the pointer position inside `image`
+    pub hotspot: (u32, u32),
+    pub position: (i32, i32),
+}
+
+///
+/// A source of frames for a region of the desktop.
+///
+/// Frames are tightly packed rows from top to bottom, `size().0 * bytes_per_pixel`
+/// bytes per row, in the layout reported by `pixel_format`.
+pub trait CaptureBackend: Send {
+    fn name(&self) -> &'static str;
+
+    /// Prepares the backend for grabbing `region`. Must be called before `grab_frame`.
+    fn open(&mut self, region: Bound2) -> CaptureResult<()>;
+
+    /// Looks up a top level window, returning its native id
+    fn find_window(&mut self, _selector: &WindowSelector) -> Option<u64> {
+        None
+    }
+
+    /// Prepares the backend for grabbing the content of a window, wherever it
+    /// moves to. The frame size is fixed to the size of the window at this point.
+    fn open_window(&mut self, _window: u64) -> CaptureResult<()> {
+        Err(CaptureError::Backend(format!(
+            "{} backend cannot capture windows",
+            self.name()
+        )))
+    }
+
+    /// Size of the frames produced for the currently opened region
+    fn size(&self) -> (u32, u32);
+
+    /// Desktop area the next frame is taken from. It only changes between
+    /// frames while following a window.
+    fn frame_bound(&mut self) -> Bound2;
+
+    fn pixel_format(&self) -> PixelFormat;
+
+    /// Grabs the current content of the opened region into `buf`. The buffer is
+    /// resized when needed so it can be reused across frames. A frame that
+    /// cannot be read fails rather than coming back blank.
+    fn grab_frame(&mut self, buf: &mut Vec<u8>) -> CaptureResult<()>;
+
+    /// Asks the backend to draw the cursor into the frames by itself. Returns
+    /// whether it does, otherwise the cursor is composited by the caller.
+    fn set_draw_cursor(&mut self, _draw: bool) -> bool {
+        false
+    }
+
+    /// The current cursor image, if the backend can read it
+    fn cursor_image(&mut self) -> Option<CursorImage> {
+        None
+    }
+
+    /// The current pointer position in desktop coordinates
+    fn cursor_position(&mut self) -> Option<(i32, i32)> {
+        None
+    }
+
+    fn capture_image(&mut self) -> CaptureResult<RgbaImage> {
+        let mut buf = Vec::new();
+        self.grab_frame(&mut buf)?;
+        let (width, height) = self.size();
+        Ok(frame_to_rgba_image(self.pixel_format(), width, height, buf))
+    }
+}
+
+///
+/// Converts a packed frame into an `RgbaImage`, swapping channels if needed
+pub fn frame_to_rgba_image(
+    format: PixelFormat,
+    width: u32,
+    height: u32,
+    mut buf: Vec<u8>,
+) -> RgbaImage {
+    if format == PixelFormat::Bgra8 {
+        frame_buffer::swizzle_rb(&mut buf);
+    }
+    buf.resize((width * height) as usize * 4, 0);
+    RgbaImage::from_raw(width, height, buf).expect("frame buffer size mismatch")
+}
+
+///
+/// Copies the part of `src`, holding the pixels of `src_bound`, that overlaps
+/// `dst_bound` into `dst`. Used to stitch frames of several monitors together.
+/// Both frames are packed 4 byte pixels of the same format.
+pub fn blit_frame(dst: &mut [u8], dst_bound: Bound2, src: &[u8], src_bound: Bound2) {
+    let part = match dst_bound.intersection(&src_bound) {
+        Some(part) => part,
+        None => return,
+    };
+    let dst_row = dst_bound.get_width() as usize * 4;
+    let src_row = src_bound.get_width() as usize * 4;
+    let len = part.get_width() as usize * 4;
+    let dst_x = (part.min.0 - dst_bound.min.0) as usize * 4;
+    let src_x = (part.min.0 - src_bound.min.0) as usize * 4;
+    for y in part.min.1..part.max.1 {
+        let d = (y - dst_bound.min.1) as usize * dst_row + dst_x;
+        let s = (y - src_bound.min.1) as usize * src_row + src_x;
+        dst[d..d + len].copy_from_slice(&src[s..s + len]);
+    }
+}
+
+#[cfg(test)]
+mod capture_backend_test {
+    use super::*;
+
+    #[test]
+    fn bgra_frame_to_rgba_test() {
+        let buf = vec![1u8, 2, 3, 4, 5, 6, 7, 8];
+        let img = frame_to_rgba_image(PixelFormat::Bgra8, 2, 1, buf);
+        assert_eq!(img.get_pixel(0, 0).0, [3, 2, 1, 4]);
+        assert_eq!(img.get_pixel(1, 0).0, [7, 6, 5, 8]);
+    }
+
+    #[test]
+    fn blit_straddling_frame_test() {
+        // a 4x2 region straddling two monitors at x = 0
+        let region = Bound2::new((-2, 0), (2, 2));
+        let mut dst = vec![0u8; 4 * 2 * 4];
+        let left = vec![1u8; 2 * 2 * 4];
+        let right = vec![2u8; 3 * 3 * 4];
+        blit_frame(&mut dst, region, &left, Bound2::new((-2, 0), (0, 2)));
+        blit_frame(&mut dst, region, &right, Bound2::new((0, -1), (3, 2)));
+
+        let pixels: Vec<u8> = dst.chunks_exact(4).map(|p| p[0]).collect();
+        assert_eq!(pixels, vec![1, 1, 2, 2, 1, 1, 2, 2]);
+    }
+}
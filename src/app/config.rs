@@ -1,11 +1,54 @@
+use super::canvas::Bound2;
+use super::capture_backend::{CaptureBackendKind, WindowSelector};
+use super::palette::{Dithering, Quantizer};
+use std::path::PathBuf;
+
+#[derive(Debug, Clone)]
 pub struct CaptureConfig {
-    pub gif_capture_fps: u32,
+    /// Settings of recordings that do not bring their own
+    pub recording: RecordingSettings,
+    pub backend: CaptureBackendKind,
+    /// Draw the mouse cursor into captured frames
+    pub capture_cursor: bool,
+    /// Scale applied to the cursor image when it is drawn
+    pub cursor_scale: f32,
+    /// Drop recorded frames that did not change, showing the previous one longer
+    pub skip_unchanged_frames: bool,
+    /// Fraction of pixels that may differ for a frame to still count as unchanged
+    pub unchanged_frame_threshold: f32,
+    /// Seconds counted down between selecting and capturing, 0 captures at once
+    pub countdown_seconds: u32,
+    /// Window captured by `WindowImageCapture` and `WindowGifCapture`, looked up
+    /// by its id or title each time
+    pub window: Option<WindowSelector>,
+    /// Format of recordings that do not ask for one
+    pub animation_format: AnimationFormat,
+    pub gif: GifConfig,
+    pub apng: ApngConfig,
+    pub webp: WebpConfig,
+    pub video: VideoConfig,
+    pub replay: ReplayConfig,
+    pub timelapse: TimelapseConfig,
 }
 
 impl CaptureConfig {
     pub fn new() -> Self {
         CaptureConfig {
-            gif_capture_fps: 15,
+            recording: RecordingSettings::default(),
+            backend: CaptureBackendKind::Native,
+            capture_cursor: false,
+            cursor_scale: 1.0,
+            skip_unchanged_frames: true,
+            unchanged_frame_threshold: 0.0,
+            countdown_seconds: 0,
+            window: None,
+            animation_format: AnimationFormat::Gif,
+            gif: GifConfig::default(),
+            apng: ApngConfig::default(),
+            webp: WebpConfig::default(),
+            video: VideoConfig::default(),
+            replay: ReplayConfig::default(),
+            timelapse: TimelapseConfig::default(),
         }
     }
 }
@@ -13,7 +56,238 @@ impl CaptureConfig {
 impl Default for CaptureConfig {
     fn default() -> Self {
         CaptureConfig {
-            gif_capture_fps: 15,
+            recording: RecordingSettings::default(),
+            backend: CaptureBackendKind::Native,
+            capture_cursor: false,
+            cursor_scale: 1.0,
+            skip_unchanged_frames: true,
+            unchanged_frame_threshold: 0.0,
+            countdown_seconds: 0,
+            window: None,
+            animation_format: AnimationFormat::Gif,
+            gif: GifConfig::default(),
+            apng: ApngConfig::default(),
+            webp: WebpConfig::default(),
+            video: VideoConfig::default(),
+            replay: ReplayConfig::default(),
+            timelapse: TimelapseConfig::default(),
+        }
+    }
+}
+
+/// Frame rate and limits of a recording, and how its animation loops
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct RecordingSettings {
+    pub fps: u32,
+    /// Seconds after which the recording ends by itself, `None` records until
+    /// it is stopped
+    pub max_duration: Option<f64>,
+    /// Frames after which the recording ends by itself
+    pub max_frames: Option<u32>,
+    /// Ignored by videos, which play once
+    pub loop_count: LoopCount,
+}
+
+impl Default for RecordingSettings {
+    fn default() -> Self {
+        RecordingSettings {
+            fps: 15,
+            max_duration: Some(30.0),
+            max_frames: None,
+            loop_count: LoopCount::Infinite,
+        }
+    }
+}
+
+/// How often an animation plays
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum LoopCount {
+    Infinite,
+    Once,
+    /// Played this many times in all, at least once
+    Times(u16),
+}
+
+impl LoopCount {
+    ///
+    /// The plays as APNG and WebP count them, 0 for ever
+    pub fn plays(&self) -> u16 {
+        match self {
+            Self::Infinite => 0,
+            Self::Once => 1,
+            Self::Times(n) => *n,
+        }
+    }
+}
+
+/// File format of a recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum AnimationFormat {
+    Gif,
+    /// Animated PNG, in full colour
+    Apng,
+    /// Animated WebP, which needs libwebp installed
+    Webp,
+    /// H.264 video, which needs ffmpeg installed
+    Mp4,
+    /// VP9 video, which needs ffmpeg installed
+    Webm,
+}
+
+impl AnimationFormat {
+    pub fn extension(&self) -> &'static str {
+        match self {
+            Self::Gif => "gif",
+            Self::Apng => "png",
+            Self::Webp => "webp",
+            Self::Mp4 => "mp4",
+            Self::Webm => "webm",
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct ApngConfig {
+    /// Write only the part of a frame that changed from the previous one
+    pub optimize_frames: bool,
+}
+
+impl Default for ApngConfig {
+    fn default() -> Self {
+        ApngConfig {
+            optimize_frames: true,
+        }
+    }
+}
+
+#[derive(Debug, Clone)]
+pub struct WebpConfig {
+    pub lossless: bool,
+    /// Quality of lossy frames, from 0 to 100
+    pub quality: f32,
+    /// Write only the part of a frame that changed from the previous one
+    pub optimize_frames: bool,
+}
+
+impl Default for WebpConfig {
+    fn default() -> Self {
+        WebpConfig {
+            lossless: false,
+            quality: 80.0,
+            optimize_frames: true,
+        }
+    }
+}
+
+/// Videos, encoded by a local ffmpeg
+#[derive(Debug, Clone)]
+pub struct VideoConfig {
+    /// The ffmpeg binary, looked up in `PATH` if it is a bare name
+    pub ffmpeg: PathBuf,
+    pub mp4: VideoPreset,
+    pub webm: VideoPreset,
+}
+
+impl Default for VideoConfig {
+    fn default() -> Self {
+        VideoConfig {
+            ffmpeg: PathBuf::from("ffmpeg"),
+            mp4: VideoPreset {
+                codec: "libx264".to_string(),
+                crf: 23,
+                options: vec!["-preset".to_string(), "veryfast".to_string()],
+            },
+            webm: VideoPreset {
+                codec: "libvpx-vp9".to_string(),
+                crf: 32,
+                // VP9 only keeps to the CRF without a target bitrate
+                options: ["-b:v", "0", "-deadline", "good", "-cpu-used", "4"]
+                    .iter()
+                    .map(|s| s.to_string())
+                    .collect(),
+            },
+        }
+    }
+}
+
+/// The ffmpeg encoder of a video format
+#[derive(Debug, Clone)]
+pub struct VideoPreset {
+    /// Name of the ffmpeg encoder
+    pub codec: String,
+    /// Constant rate factor, lower is better and larger, from 0 to 63
+    pub crf: u32,
+    /// Further encoder options, passed to ffmpeg as they are
+    pub options: Vec<String>,
+}
+
+/// How the frames of a GIF are reduced to their palette
+#[derive(Debug, Clone)]
+pub struct GifConfig {
+    pub quantizer: Quantizer,
+    pub dithering: Dithering,
+    /// Colours in a palette, from 2 to 256. Optimized frames keep one of 256
+    /// for transparency.
+    pub max_colors: u16,
+    /// Write only the part of a frame that changed from the previous one
+    pub optimize_frames: bool,
+}
+
+impl Default for GifConfig {
+    fn default() -> Self {
+        GifConfig {
+            quantizer: Quantizer::NeuQuant { sample_factor: 1 },
+            dithering: Dithering::None,
+            max_colors: 256,
+            optimize_frames: true,
+        }
+    }
+}
+
+/// Instant replay, recording a region all the time to save its last seconds
+#[derive(Debug, Clone)]
+pub struct ReplayConfig {
+    /// Region recorded in the background, `None` disables instant replay
+    pub region: Option<Bound2>,
+    /// How far back a saved replay reaches
+    pub seconds: f64,
+    pub fps: u32,
+    /// Upper bound of the memory held by the buffered frames, in bytes
+    pub memory_limit: usize,
+    /// Deflate the buffered frames, trading CPU time for memory
+    pub compress: bool,
+}
+
+impl Default for ReplayConfig {
+    fn default() -> Self {
+        ReplayConfig {
+            region: None,
+            seconds: 10.0,
+            fps: 10,
+            memory_limit: 256 << 20,
+            compress: true,
+        }
+    }
+}
+
+/// Timelapse, taking a frame every few seconds or minutes over a long time
+#[derive(Debug, Clone)]
+pub struct TimelapseConfig {
+    /// Seconds between two frames
+    pub interval: f64,
+    /// Frame rate the timelapse is played back at
+    pub playback_fps: u32,
+    /// Where the frames are kept until the timelapse is assembled, the
+    /// temporary directory if `None`
+    pub frame_dir: Option<PathBuf>,
+}
+
+impl Default for TimelapseConfig {
+    fn default() -> Self {
+        TimelapseConfig {
+            interval: 60.0,
+            playback_fps: 10,
+            frame_dir: None,
         }
     }
 }
@@ -0,0 +1,175 @@
+//! Conversions between logical and physical desktop coordinates.
+//!
+//! Capture regions are `Bound2`s in physical pixels of the virtual desktop. With
+//! per-monitor scale factors each monitor keeps its origin in both spaces and
+//! only the distances inside of it are scaled, which is how compositors with
+//! per-output scaling lay out their outputs.
+
+use super::canvas::Bound2;
+
+pub use glutin::dpi::{LogicalPosition, PhysicalPosition};
+
+#[derive(Debug, Clone, Copy, PartialEq)]
+pub struct MonitorInfo {
+    /// Physical pixels covered by the monitor
+    pub bound: Bound2,
+    pub scale_factor: f64,
+}
+
+impl MonitorInfo {
+    fn contains_logical(&self, pos: LogicalPosition<f64>) -> bool {
+        let (min, size) = (self.bound.min, self.logical_size());
+        pos.x >= min.0 as f64
+            && pos.y >= min.1 as f64
+            && pos.x < min.0 as f64 + size.0
+            && pos.y < min.1 as f64 + size.1
+    }
+
+    fn logical_size(&self) -> (f64, f64) {
+        (
+            self.bound.get_width() as f64 / self.scale_factor,
+            self.bound.get_height() as f64 / self.scale_factor,
+        )
+    }
+}
+
+/// The monitors making up the virtual desktop
+#[derive(Debug, Clone, PartialEq)]
+pub struct DesktopLayout {
+    pub monitors: Vec<MonitorInfo>,
+}
+
+impl DesktopLayout {
+    pub fn new(monitors: Vec<MonitorInfo>) -> Self {
+        DesktopLayout { monitors }
+    }
+
+    ///
+    /// The bound of all monitors together
+    pub fn virtual_bound(&self) -> Bound2 {
+        self.monitors
+            .iter()
+            .map(|m| m.bound)
+            .reduce(|desktop, bound| desktop.union(&bound))
+            .unwrap_or_default()
+    }
+
+    pub fn monitor_at(&self, pos: PhysicalPosition<i32>) -> Option<&MonitorInfo> {
+        self.monitors.iter().find(|m| {
+            let b = m.bound;
+            pos.x >= b.min.0 && pos.y >= b.min.1 && pos.x < b.max.0 && pos.y < b.max.1
+        })
+    }
+
+    ///
+    /// Points outside of every monitor are left unscaled
+    pub fn to_logical(&self, pos: PhysicalPosition<i32>) -> LogicalPosition<f64> {
+        match self.monitor_at(pos) {
+            Some(m) => LogicalPosition::new(
+                m.bound.min.0 as f64 + (pos.x - m.bound.min.0) as f64 / m.scale_factor,
+                m.bound.min.1 as f64 + (pos.y - m.bound.min.1) as f64 / m.scale_factor,
+            ),
+            None => LogicalPosition::new(pos.x as f64, pos.y as f64),
+        }
+    }
+
+    ///
+    /// Points outside of every monitor are left unscaled
+    pub fn to_physical(&self, pos: LogicalPosition<f64>) -> PhysicalPosition<i32> {
+        match self.monitors.iter().find(|m| m.contains_logical(pos)) {
+            Some(m) => PhysicalPosition::new(
+                m.bound.min.0 + ((pos.x - m.bound.min.0 as f64) * m.scale_factor).round() as i32,
+                m.bound.min.1 + ((pos.y - m.bound.min.1 as f64) * m.scale_factor).round() as i32,
+            ),
+            None => PhysicalPosition::new(pos.x.round() as i32, pos.y.round() as i32),
+        }
+    }
+
+    ///
+    /// Maps a position inside a window placed at `origin` to the desktop. On
+    /// platforms where windows live in the logical space, the position is in
+    /// physical pixels of the window's own `scale_factor`, which can differ
+    /// from the monitor the point is on.
+    pub fn window_to_desktop(
+        &self,
+        origin: PhysicalPosition<i32>,
+        pos: PhysicalPosition<f64>,
+        scale_factor: f64,
+        logical_window: bool,
+    ) -> PhysicalPosition<i32> {
+        if !logical_window {
+            return PhysicalPosition::new(
+                origin.x + pos.x.round() as i32,
+                origin.y + pos.y.round() as i32,
+            );
+        }
+        let origin = self.to_logical(origin);
+        let pos: LogicalPosition<f64> = pos.to_logical(scale_factor);
+        self.to_physical(LogicalPosition::new(origin.x + pos.x, origin.y + pos.y))
+    }
+}
+
+#[cfg(test)]
+mod coords_test {
+    use super::*;
+
+    /// A 1x monitor with a 2x one of 1280x720 logical pixels on its right
+    fn mixed_dpi_layout() -> DesktopLayout {
+        DesktopLayout::new(vec![
+            MonitorInfo {
+                bound: Bound2::new((0, 0), (1920, 1080)),
+                scale_factor: 1.0,
+            },
+            MonitorInfo {
+                bound: Bound2::new((1920, 0), (1920 + 2560, 1440)),
+                scale_factor: 2.0,
+            },
+        ])
+    }
+
+    #[test]
+    fn logical_physical_round_trip_test() {
+        let layout = mixed_dpi_layout();
+        assert_eq!(
+            layout.virtual_bound(),
+            Bound2::new((0, 0), (1920 + 2560, 1440))
+        );
+
+        let physical = PhysicalPosition::new(1920 + 200, 100);
+        let logical = layout.to_logical(physical);
+        assert_eq!(logical, LogicalPosition::new(1920.0 + 100.0, 50.0));
+        assert_eq!(layout.to_physical(logical), physical);
+
+        let physical = PhysicalPosition::new(100, 100);
+        assert_eq!(
+            layout.to_logical(physical),
+            LogicalPosition::new(100.0, 100.0)
+        );
+        assert_eq!(
+            layout.to_physical(LogicalPosition::new(100.0, 100.0)),
+            physical
+        );
+    }
+
+    #[test]
+    fn window_to_desktop_test() {
+        let layout = mixed_dpi_layout();
+        let origin = PhysicalPosition::new(0, 0);
+
+        // physical window coordinates are desktop pixels already
+        let pos = layout.window_to_desktop(origin, PhysicalPosition::new(2000.0, 10.0), 2.0, false);
+        assert_eq!(pos, PhysicalPosition::new(2000, 10));
+
+        // a window with scale 2 spanning both monitors reports 2 pixels per
+        // logical pixel, also on the 1x monitor
+        let pos = layout.window_to_desktop(origin, PhysicalPosition::new(200.0, 10.0), 2.0, true);
+        assert_eq!(pos, PhysicalPosition::new(100, 5));
+        let pos = layout.window_to_desktop(
+            origin,
+            PhysicalPosition::new(2.0 * (1920.0 + 100.0), 20.0),
+            2.0,
+            true,
+        );
+        assert_eq!(pos, PhysicalPosition::new(1920 + 200, 20));
+    }
+}
@@ -0,0 +1,113 @@
+use super::canvas::Bound2;
+use super::capture_backend::{CaptureBackend, CursorImage};
+
+use image::{imageops, Rgba, RgbaImage};
+
+/// Arrow pointer used when the backend cannot provide the real cursor image.
+/// `X` is the outline, `.` the fill and spaces are transparent.
+const ARROW: [&str; 19] = [
+    "X          ",
+    "XX         ",
+    "X.X        ",
+    "X..X       ",
+    "X...X      ",
+    "X....X     ",
+    "X.....X    ",
+    "X......X   ",
+    "X.......X  ",
+    "X........X ",
+    "X.....XXXXX",
+    "X..X..X    ",
+    "X.X X..X   ",
+    "XX  X..X   ",
+    "X    X..X  ",
+    "     X..X  ",
+    "      X..X ",
+    "      X..X ",
+    "       XX  ",
+];
+
+///
+/// Returns the built-in arrow pointer at `position`, with its hotspot on the tip
+pub fn synthetic_cursor(position: (i32, i32)) -> CursorImage {
+    let mut image = RgbaImage::new(ARROW[0].len() as u32, ARROW.len() as u32);
+    for (y, row) in ARROW.iter().enumerate() {
+        for (x, c) in row.bytes().enumerate() {
+            let color = match c {
+                b'X' => Rgba([0, 0, 0, 0xff]),
+                b'.' => Rgba([0xff, 0xff, 0xff, 0xff]),
+                _ => continue,
+            };
+            image.put_pixel(x as u32, y as u32, color);
+        }
+    }
+    CursorImage {
+        image,
+        hotspot: (0, 0),
+        position,
+    }
+}
+
+///
+/// Draws the cursor onto `frame`, which holds the content of `bound`. The cursor
+/// image is scaled by `scale` around its hotspot.
+pub fn composite_cursor(frame: &mut RgbaImage, bound: Bound2, cursor: &CursorImage, scale: f32) {
+    let scale = if scale > 0.0 { scale } else { 1.0 };
+    let (width, height) = cursor.image.dimensions();
+    let scaled;
+    let image = if (scale - 1.0).abs() > f32::EPSILON {
+        let w = ((width as f32 * scale).round() as u32).max(1);
+        let h = ((height as f32 * scale).round() as u32).max(1);
+        scaled = imageops::resize(&cursor.image, w, h, imageops::FilterType::Triangle);
+        &scaled
+    } else {
+        &cursor.image
+    };
+    let hotspot = (
+        (cursor.hotspot.0 as f32 * scale).round() as i64,
+        (cursor.hotspot.1 as f32 * scale).round() as i64,
+    );
+    let x = (cursor.position.0 - bound.min.0) as i64 - hotspot.0;
+    let y = (cursor.position.1 - bound.min.1) as i64 - hotspot.1;
+    imageops::overlay(frame, image, x, y);
+}
+
+///
+/// Draws the cursor reported by `backend` onto `frame`, falling back to the
+/// synthetic arrow when only the pointer position is known
+pub fn draw_backend_cursor(
+    frame: &mut RgbaImage,
+    bound: Bound2,
+    backend: &mut dyn CaptureBackend,
+    scale: f32,
+) {
+    let cursor = backend
+        .cursor_image()
+        .or_else(|| backend.cursor_position().map(synthetic_cursor));
+    if let Some(cursor) = cursor {
+        composite_cursor(frame, bound, &cursor, scale);
+    }
+}
+
+#[cfg(test)]
+mod cursor_test {
+    use super::*;
+
+    #[test]
+    fn composite_scaled_cursor_at_hotspot_test() {
+        let bound = Bound2::new((100, 100), (164, 164));
+        let mut frame = RgbaImage::from_pixel(64, 64, Rgba([0x80, 0x80, 0x80, 0xff]));
+        let cursor = CursorImage {
+            image: RgbaImage::from_pixel(2, 2, Rgba([0xff, 0, 0, 0xff])),
+            hotspot: (1, 1),
+            position: (110, 120),
+        };
+        composite_cursor(&mut frame, bound, &cursor, 2.0);
+
+        // the 4x4 scaled cursor starts 2 pixels up and left of the pointer
+        assert_eq!(frame.get_pixel(8, 18).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(frame.get_pixel(11, 21).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(frame.get_pixel(7, 18).0, [0x80, 0x80, 0x80, 0xff]);
+        assert_eq!(frame.get_pixel(12, 21).0, [0x80, 0x80, 0x80, 0xff]);
+    }
+}
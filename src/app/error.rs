@@ -0,0 +1,77 @@
+use std::fmt;
+
+pub type CaptureResult<T> = Result<T, CaptureError>;
+
+/// Why a capture, its encoding or saving it failed
+#[derive(Debug)]
+pub enum CaptureError {
+    /// The backend cannot capture the requested target
+    Backend(String),
+    /// Capture parameters out of range
+    InvalidParameter(String),
+    /// The capture ended before any frame was taken
+    NoFrames,
+    /// The capture was ended without saving it
+    Cancelled,
+    /// A capture of this kind is running already
+    Busy(&'static str),
+    /// The external encoder at this path is not installed
+    EncoderNotFound(String),
+    /// The frames could not be encoded
+    Encoding(Box<dyn std::error::Error + Send + Sync>),
+    /// Reading or writing a file failed
+    Io(std::io::Error),
+}
+
+impl fmt::Display for CaptureError {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Self::Backend(msg) => write!(f, "capture backend error: {}", msg),
+            Self::InvalidParameter(msg) => write!(f, "invalid capture parameter: {}", msg),
+            Self::NoFrames => write!(f, "no frames were captured"),
+            Self::Cancelled => write!(f, "the capture was cancelled"),
+            Self::Busy(kind) => write!(f, "a {} is running already", kind),
+            Self::EncoderNotFound(path) => write!(
+                f,
+                "encoder `{}` was not found, install it or set its path in the config",
+                path
+            ),
+            Self::Encoding(e) => write!(f, "encoding failed: {}", e),
+            Self::Io(e) => write!(f, "I/O error: {}", e),
+        }
+    }
+}
+
+impl std::error::Error for CaptureError {
+    fn source(&self) -> Option<&(dyn std::error::Error + 'static)> {
+        match self {
+            Self::Encoding(e) => Some(e.as_ref()),
+            Self::Io(e) => Some(e),
+            _ => None,
+        }
+    }
+}
+
+impl From<std::io::Error> for CaptureError {
+    fn from(e: std::io::Error) -> Self {
+        Self::Io(e)
+    }
+}
+
+impl From<gif::EncodingError> for CaptureError {
+    fn from(e: gif::EncodingError) -> Self {
+        match e {
+            gif::EncodingError::Io(e) => Self::Io(e),
+            e => Self::Encoding(Box::new(e)),
+        }
+    }
+}
+
+impl From<image::ImageError> for CaptureError {
+    fn from(e: image::ImageError) -> Self {
+        match e {
+            image::ImageError::IoError(e) => Self::Io(e),
+            e => Self::Encoding(Box::new(e)),
+        }
+    }
+}
@@ -1,10 +1,15 @@
 use glutin::{
-    dpi::{LogicalPosition, PhysicalPosition},
+    dpi::{LogicalPosition, PhysicalPosition, PhysicalSize},
     event::{ElementState, KeyboardInput, MouseButton, VirtualKeyCode, WindowEvent},
 };
 
+use std::sync::Arc;
+
 use super::{
     action::Action,
+    canvas::RecordingState,
+    error::CaptureError,
+    progress::CaptureProgress,
     window::{AppWindow, Target},
 };
 
@@ -19,13 +24,22 @@ pub struct KeyInputData {
     pub virtual_keycode: VirtualKeyCode,
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub enum Event {
     InvokeRegionSelector(Action),
     DoAction(Action),
+    /// Seconds left until the pending capture starts, 0 once it does
+    Countdown(u32),
+    /// The GIF recording started, paused or resumed
+    Recording(RecordingState),
+    /// How a recording goes, and where it ended up
+    Progress(CaptureProgress),
+    /// A capture, or encoding and saving it, failed. Sent from the capture
+    /// tasks as well, so the error is shared.
+    CaptureFailed(Arc<CaptureError>),
 }
 
-#[derive(Debug, Clone, Copy)]
+#[derive(Debug, Clone)]
 pub struct UserEvent {
     pub sender: Target,
     pub receiver: Target,
@@ -68,4 +82,7 @@ pub trait WindowEventHandler {
     fn send_user_event(&self, receiver: Target, event: Event);
 
     fn on_focus_event(&mut self, focus: bool);
+
+    /// The framebuffer was resized, also when moved to a monitor with another scale
+    fn on_resize_event(&mut self, size: PhysicalSize<u32>);
 }
@@ -0,0 +1,155 @@
+//! Animation frames are drawn over what the previous frames left on screen, so
+//! only the part of a frame that changed has to be written. In a GIF the pixels
+//! inside that part which already show the right colour are left transparent,
+//! which compresses far better than repeating them.
+
+use super::palette::Palette;
+
+use image::RgbaImage;
+
+/// The changed part of a frame, in palette indices
+#[derive(Debug, PartialEq, Eq)]
+pub struct FrameDelta {
+    pub left: u16,
+    pub top: u16,
+    pub width: u16,
+    pub height: u16,
+    pub indices: Vec<u8>,
+}
+
+///
+/// The colours on screen once the frames written so far are drawn
+pub struct ShownFrame {
+    width: usize,
+    height: usize,
+    pixels: Vec<[u8; 3]>,
+}
+
+impl ShownFrame {
+    ///
+    /// The screen after the first frame, which is written whole
+    pub fn new(width: usize, height: usize, indices: &[u8], palette: &Palette) -> Self {
+        ShownFrame {
+            width,
+            height,
+            pixels: indices.iter().map(|&i| palette.color(i)).collect(),
+        }
+    }
+
+    pub fn dimensions(&self) -> (usize, usize) {
+        (self.width, self.height)
+    }
+
+    ///
+    /// Draws the frame of `indices` into `palette` and returns the smallest
+    /// rect holding the pixels that changed, the ones that did not marked
+    /// `transparent`. A frame without changes still gives a single transparent
+    /// pixel, for its delay to be kept.
+    pub fn apply(&mut self, indices: &[u8], palette: &Palette, transparent: u8) -> FrameDelta {
+        let (mut left, mut top) = (self.width, self.height);
+        let (mut right, mut bottom) = (0, 0);
+        let mut colors = Vec::with_capacity(indices.len());
+        for (i, &index) in indices.iter().enumerate() {
+            let color = palette.color(index);
+            if color != self.pixels[i] {
+                let (x, y) = (i % self.width, i / self.width);
+                left = left.min(x);
+                right = right.max(x + 1);
+                top = top.min(y);
+                bottom = bottom.max(y + 1);
+            }
+            colors.push(color);
+        }
+        if left >= right {
+            return FrameDelta {
+                left: 0,
+                top: 0,
+                width: 1,
+                height: 1,
+                indices: vec![transparent],
+            };
+        }
+
+        let mut delta = Vec::with_capacity((right - left) * (bottom - top));
+        for y in top..bottom {
+            for x in left..right {
+                let i = y * self.width + x;
+                if colors[i] == self.pixels[i] {
+                    delta.push(transparent);
+                } else {
+                    delta.push(indices[i]);
+                    self.pixels[i] = colors[i];
+                }
+            }
+        }
+        FrameDelta {
+            left: left as u16,
+            top: top as u16,
+            width: (right - left) as u16,
+            height: (bottom - top) as u16,
+            indices: delta,
+        }
+    }
+}
+
+///
+/// The smallest rect `(x, y, width, height)` holding the pixels of `image` that
+/// differ from `prev`, `None` if none does
+pub fn changed_bounds(prev: &RgbaImage, image: &RgbaImage) -> Option<(u32, u32, u32, u32)> {
+    let (width, height) = image.dimensions();
+    let (mut left, mut top, mut right, mut bottom) = (width, height, 0, 0);
+    for (x, y, pixel) in image.enumerate_pixels() {
+        if prev.get_pixel(x, y) != pixel {
+            left = left.min(x);
+            right = right.max(x + 1);
+            top = top.min(y);
+            bottom = bottom.max(y + 1);
+        }
+    }
+    match left < right {
+        true => Some((left, top, right - left, bottom - top)),
+        false => None,
+    }
+}
+
+#[cfg(test)]
+mod frame_delta_test {
+    use super::*;
+
+    #[test]
+    fn changed_rect_test() {
+        let palette = Palette::new(vec![[0, 0, 0], [255, 255, 255], [255, 0, 0]]);
+        let transparent = 3;
+        let mut shown = ShownFrame::new(4, 3, &[0; 12], &palette);
+
+        #[rustfmt::skip]
+        let frame = [
+            0, 0, 0, 0,
+            0, 1, 0, 2,
+            0, 0, 1, 0,
+        ];
+        let delta = shown.apply(&frame, &palette, transparent);
+        assert_eq!(
+            (delta.left, delta.top, delta.width, delta.height),
+            (1, 1, 3, 2)
+        );
+        assert_eq!(delta.indices, vec![1, 3, 2, 3, 1, 3]);
+
+        // drawn over the previous frame, only the red pixel goes back to black
+        #[rustfmt::skip]
+        let frame = [
+            0, 0, 0, 0,
+            0, 1, 0, 0,
+            0, 0, 1, 0,
+        ];
+        let delta = shown.apply(&frame, &palette, transparent);
+        assert_eq!(
+            (delta.left, delta.top, delta.width, delta.height),
+            (3, 1, 1, 1)
+        );
+        assert_eq!(delta.indices, vec![0]);
+
+        let delta = shown.apply(&frame, &palette, transparent);
+        assert_eq!(delta.indices, vec![transparent]);
+    }
+}
@@ -4,5 +4,80 @@ pub trait Graphics {
 
     fn draw_rect_frame(&self, x: i32, y: i32, w: u32, h: u32);
 
+    /// Fills a rect with `color`, alpha included
+    fn fill_rect(&self, x: i32, y: i32, w: u32, h: u32, color: (f32, f32, f32, f32));
+
+    /// Draws `value` in digits of `height` pixels centered on (x, y)
+    fn draw_number(&self, x: i32, y: i32, height: u32, value: u32) {
+        for (x, y, w, h) in number_segments((x, y), height, value) {
+            self.fill_rect(x, y, w, h, (1.0, 1.0, 1.0, 0.9));
+        }
+    }
+
     fn clear(&self, color: (f32, f32, f32, f32));
+
+    /// Sets the size of the framebuffer in physical pixels, which is the
+    /// coordinate space of the draw calls
+    fn resize(&self, width: u32, height: u32);
+}
+
+/// Lit segments of the digits 0 to 9, bit 0 to 6 for segment a to g
+const DIGIT_SEGMENTS: [u8; 10] = [0x3f, 0x06, 0x5b, 0x4f, 0x66, 0x6d, 0x7d, 0x07, 0x7f, 0x6f];
+
+///
+/// The rects of `value` written in seven segment digits of `height` pixels,
+/// centered on `center`
+pub fn number_segments(center: (i32, i32), height: u32, value: u32) -> Vec<(i32, i32, u32, u32)> {
+    let digits: Vec<usize> = value
+        .to_string()
+        .bytes()
+        .map(|b| (b - b'0') as usize)
+        .collect();
+    let (h, w) = (height as i32, (height / 2) as i32);
+    let t = (h / 8).max(1);
+    let gap = 2 * t;
+    let width = digits.len() as i32 * (w + gap) - gap;
+    let (mut x, y) = (center.0 - width / 2, center.1 - h / 2);
+    let half = h / 2;
+    let segments = [
+        (0, 0, w, t),
+        (w - t, 0, t, half),
+        (w - t, half, t, h - half),
+        (0, h - t, w, t),
+        (0, half, t, h - half),
+        (0, 0, t, half),
+        (0, (h - t) / 2, w, t),
+    ];
+
+    let mut rects = Vec::new();
+    for digit in digits {
+        for (i, (sx, sy, sw, sh)) in segments.iter().enumerate() {
+            if DIGIT_SEGMENTS[digit] & (1 << i) != 0 {
+                rects.push((x + sx, y + sy, *sw as u32, *sh as u32));
+            }
+        }
+        x += w + gap;
+    }
+    rects
+}
+
+#[cfg(test)]
+mod graphics_test {
+    use super::*;
+
+    #[test]
+    fn number_segments_test() {
+        assert_eq!(number_segments((0, 0), 80, 8).len(), 7);
+        assert_eq!(number_segments((0, 0), 80, 1).len(), 2);
+
+        // a 1 only lights its right side, 8s fill the whole box
+        assert_eq!(number_segments((0, 0), 80, 10).len(), 2 + 6);
+        let rects = number_segments((500, 300), 80, 88);
+        let left = rects.iter().map(|r| r.0).min().unwrap();
+        let right = rects.iter().map(|r| r.0 + r.2 as i32).max().unwrap();
+        let top = rects.iter().map(|r| r.1).min().unwrap();
+        let bottom = rects.iter().map(|r| r.1 + r.3 as i32).max().unwrap();
+        assert_eq!((left + right) / 2, 500);
+        assert_eq!((top, bottom), (260, 340));
+    }
 }
@@ -6,11 +6,14 @@ use glm::{length, vec3, Matrix3};
 use glutin::ContextWrapper;
 use glutin::{window::Window, Context, PossiblyCurrent};
 
-use std::{cell::RefCell, ops::Mul};
+use std::{
+    cell::{Cell, RefCell},
+    ops::Mul,
+};
 
 pub struct GraphicsOpenGLImpl {
     pub render_api: RefCell<Gl>,
-    pub desktop_size: (u32, u32),
+    pub viewport_size: Cell<(u32, u32)>,
 }
 
 ///
@@ -40,10 +43,15 @@ mod opengl_backend_test {
 impl Graphics for GraphicsOpenGLImpl {
     #[inline(always)]
     fn draw_rect(&self, x: i32, y: i32, w: u32, h: u32) {
+        self.fill_rect(x, y, w, h, (0.0, 0.0, 0.0, 0.1));
+    }
+
+    fn fill_rect(&self, x: i32, y: i32, w: u32, h: u32, color: (f32, f32, f32, f32)) {
         // Calc transform
         //
         let (x, y, w, h) = (x as f32, y as f32, w as f32, h as f32);
-        let mat = screen_to_ndc_mat(self.desktop_size.0, self.desktop_size.1);
+        let (width, height) = self.viewport_size.get();
+        let mat = screen_to_ndc_mat(width, height);
 
         let topleft = vec3(x, y, 1.0);
         let topright = vec3(x + w, y, 1.0);
@@ -55,28 +63,33 @@ impl Graphics for GraphicsOpenGLImpl {
         let bottomleft = mat * bottomleft;
         let bottomright = mat * bottomright;
 
+        let (r, g, b, a) = color;
         //self.render_api.borrow().update_uniform_mat3(mat);
         self.render_api.borrow().draw_rect_vertex(&[
             topleft.x,
             topleft.y,
-            0.0,
-            0.0,
-            0.0,
+            r,
+            g,
+            b,
+            a,
             topright.x,
             topright.y,
-            0.0,
-            0.0,
-            0.0,
+            r,
+            g,
+            b,
+            a,
             bottomleft.x,
             bottomleft.y,
-            0.0,
-            0.0,
-            0.0,
+            r,
+            g,
+            b,
+            a,
             bottomright.x,
             bottomright.y,
-            0.0,
-            0.0,
-            0.0,
+            r,
+            g,
+            b,
+            a,
         ]);
     }
 
@@ -89,4 +102,9 @@ impl Graphics for GraphicsOpenGLImpl {
             .borrow()
             .clear([color.0, color.1, color.2, color.3]);
     }
+
+    fn resize(&self, width: u32, height: u32) {
+        self.viewport_size.set((width, height));
+        self.render_api.borrow().viewport(width, height);
+    }
 }
@@ -1,9 +1,24 @@
 pub mod action;
+pub mod apng;
 pub mod application;
 pub mod canvas;
 pub mod capture;
+pub mod capture_backend;
 pub mod config;
+pub mod coords;
+pub mod cursor;
+pub mod error;
 pub mod event;
+pub mod frame_buffer;
+pub mod frame_delta;
 pub mod graphics;
 pub mod graphics_impl;
+pub mod palette;
+pub mod partial_file;
+pub mod progress;
+pub mod replay;
+pub mod synthetic_capture;
+pub mod timelapse;
+pub mod video;
+pub mod webp;
 pub mod window;
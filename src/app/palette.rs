@@ -0,0 +1,354 @@
+//! Reducing frames to the palette of at most 256 colours a GIF frame can hold.
+//!
+//! Captured frames are opaque, so the alpha channel is ignored and palettes are
+//! plain RGB.
+
+use image::RgbaImage;
+
+/// How the palette of a GIF frame is chosen
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Quantizer {
+    /// A palette per frame trained by NeuQuant. `sample_factor` from 1 to 30
+    /// trades quality for speed, 1 looks at every pixel.
+    NeuQuant { sample_factor: i32 },
+    /// A palette per frame split by median cut
+    MedianCut,
+    /// One palette for the whole GIF, split by median cut from all frames,
+    /// which keeps colours from flickering between frames
+    GlobalPalette,
+}
+
+/// How colours between the palette entries are approximated
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub enum Dithering {
+    /// Each pixel takes the nearest palette colour
+    None,
+    /// The error of each pixel is spread over its neighbours
+    FloydSteinberg,
+    /// A fixed 4x4 Bayer pattern is added before taking the nearest colour
+    Ordered,
+}
+
+/// Pixels sampled at most when a palette is made
+const MAX_SAMPLES: usize = 1 << 16;
+
+/// Bits per channel of the nearest colour lookup
+const LOOKUP_BITS: u32 = 6;
+
+///
+/// Palette colours and a lookup of the nearest one, filled in as colours come up
+pub struct Palette {
+    colors: Vec<[u8; 3]>,
+    /// Nearest colour per cell of the colour cube, once it was looked up
+    lookup: Vec<Option<u8>>,
+}
+
+impl Palette {
+    pub fn new(colors: Vec<[u8; 3]>) -> Self {
+        let colors = match colors.is_empty() {
+            true => vec![[0, 0, 0]],
+            false => colors,
+        };
+        Palette {
+            colors,
+            lookup: vec![None; 1 << (3 * LOOKUP_BITS)],
+        }
+    }
+
+    ///
+    /// Trains NeuQuant on the RGBA `pixels`
+    pub fn neuquant(pixels: &[u8], sample_factor: i32, max_colors: usize) -> Self {
+        let quant = color_quant::NeuQuant::new(sample_factor, max_colors, pixels);
+        let colors = quant
+            .color_map_rgb()
+            .chunks_exact(3)
+            .map(|c| [c[0], c[1], c[2]])
+            .collect();
+        Self::new(colors)
+    }
+
+    ///
+    /// Splits the colour space of `samples` by median cut, halving the box with
+    /// the widest channel until there are `max_colors` boxes, each averaged to
+    /// one colour
+    pub fn median_cut(samples: Vec<[u8; 3]>, max_colors: usize) -> Self {
+        let mut boxes = vec![samples];
+        while boxes.len() < max_colors {
+            let widest = boxes
+                .iter()
+                .enumerate()
+                .map(|(i, pixels)| {
+                    let (channel, range) = widest_channel(pixels);
+                    (i, channel, range)
+                })
+                .max_by_key(|(_, _, range)| *range);
+            let (i, channel) = match widest {
+                Some((i, channel, range)) if range > 0 => (i, channel),
+                _ => break,
+            };
+            let mut lower = boxes.swap_remove(i);
+            lower.sort_unstable_by_key(|p| p[channel]);
+            let upper = lower.split_off(lower.len() / 2);
+            boxes.push(lower);
+            boxes.push(upper);
+        }
+        let colors = boxes
+            .iter()
+            .filter(|pixels| !pixels.is_empty())
+            .map(|pixels| {
+                let mut sum = [0usize; 3];
+                for p in pixels {
+                    for c in 0..3 {
+                        sum[c] += p[c] as usize;
+                    }
+                }
+                let n = pixels.len();
+                [
+                    ((sum[0] + n / 2) / n) as u8,
+                    ((sum[1] + n / 2) / n) as u8,
+                    ((sum[2] + n / 2) / n) as u8,
+                ]
+            })
+            .collect();
+        Self::new(colors)
+    }
+
+    pub fn len(&self) -> usize {
+        self.colors.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.colors.is_empty()
+    }
+
+    pub fn color(&self, index: u8) -> [u8; 3] {
+        self.colors[index as usize]
+    }
+
+    ///
+    /// The colours as consecutive RGB triplets, the layout of a GIF colour table
+    pub fn to_rgb(&self) -> Vec<u8> {
+        self.colors.iter().flatten().copied().collect()
+    }
+
+    ///
+    /// Index of the palette colour nearest to `rgb`
+    pub fn nearest(&mut self, rgb: [u8; 3]) -> u8 {
+        let shift = 8 - LOOKUP_BITS;
+        let cell = ((rgb[0] as usize >> shift) << (2 * LOOKUP_BITS))
+            | ((rgb[1] as usize >> shift) << LOOKUP_BITS)
+            | (rgb[2] as usize >> shift);
+        if let Some(index) = self.lookup[cell] {
+            return index;
+        }
+        // the centre of the cell stands for all colours in it
+        let half = 1 << (shift - 1);
+        let center = [
+            (rgb[0] >> shift << shift) | half,
+            (rgb[1] >> shift << shift) | half,
+            (rgb[2] >> shift << shift) | half,
+        ];
+        let index = self.search(center);
+        self.lookup[cell] = Some(index);
+        index
+    }
+
+    fn search(&self, rgb: [u8; 3]) -> u8 {
+        let distance = |c: &[u8; 3]| -> i32 {
+            (0..3)
+                .map(|i| {
+                    let d = c[i] as i32 - rgb[i] as i32;
+                    d * d
+                })
+                .sum()
+        };
+        self.colors
+            .iter()
+            .enumerate()
+            .min_by_key(|(_, c)| distance(c))
+            .map(|(i, _)| i as u8)
+            .unwrap_or(0)
+    }
+
+    ///
+    /// Maps the pixels of `image` to indices into the palette
+    pub fn index_image(&mut self, image: &RgbaImage, dithering: Dithering) -> Vec<u8> {
+        match dithering {
+            Dithering::None => image
+                .pixels()
+                .map(|p| self.nearest([p[0], p[1], p[2]]))
+                .collect(),
+            Dithering::FloydSteinberg => self.index_floyd_steinberg(image),
+            Dithering::Ordered => self.index_ordered(image),
+        }
+    }
+
+    fn index_floyd_steinberg(&mut self, image: &RgbaImage) -> Vec<u8> {
+        let (width, height) = (image.width() as usize, image.height() as usize);
+        let mut indices = Vec::with_capacity(width * height);
+        // errors of this and the next row in sixteenths, with a cell of margin
+        // on either side
+        let mut row = vec![[0i32; 3]; width + 2];
+        let mut next = vec![[0i32; 3]; width + 2];
+        for y in 0..height {
+            for x in 0..width {
+                let p = image.get_pixel(x as u32, y as u32);
+                let mut value = [0u8; 3];
+                for c in 0..3 {
+                    value[c] = (p[c] as i32 + row[x + 1][c] / 16).clamp(0, 255) as u8;
+                }
+                let index = self.nearest(value);
+                let color = self.colors[index as usize];
+                for c in 0..3 {
+                    let error = value[c] as i32 - color[c] as i32;
+                    row[x + 2][c] += error * 7;
+                    next[x][c] += error * 3;
+                    next[x + 1][c] += error * 5;
+                    next[x + 2][c] += error;
+                }
+                indices.push(index);
+            }
+            std::mem::swap(&mut row, &mut next);
+            next.iter_mut().for_each(|e| *e = [0; 3]);
+        }
+        indices
+    }
+
+    fn index_ordered(&mut self, image: &RgbaImage) -> Vec<u8> {
+        const BAYER: [[i32; 4]; 4] = [[0, 8, 2, 10], [12, 4, 14, 6], [3, 11, 1, 9], [15, 7, 13, 5]];
+        // about the step between palette colours along a channel
+        let spread = (255.0 / (self.len() as f32).cbrt()) as i32;
+        image
+            .enumerate_pixels()
+            .map(|(x, y, p)| {
+                let offset = (2 * BAYER[y as usize % 4][x as usize % 4] - 15) * spread / 32;
+                let value = [
+                    (p[0] as i32 + offset).clamp(0, 255) as u8,
+                    (p[1] as i32 + offset).clamp(0, 255) as u8,
+                    (p[2] as i32 + offset).clamp(0, 255) as u8,
+                ];
+                self.nearest(value)
+            })
+            .collect::<Vec<_>>()
+    }
+}
+
+///
+/// Every so many pixels of `images`, at most `MAX_SAMPLES` of them all together
+pub fn sample_pixels<'a, I>(images: I) -> Vec<[u8; 3]>
+where
+    I: IntoIterator<Item = &'a RgbaImage>,
+    I::IntoIter: Clone,
+{
+    let images = images.into_iter();
+    let total: usize = images
+        .clone()
+        .map(|image| (image.width() * image.height()) as usize)
+        .sum();
+    let step = (total / MAX_SAMPLES).max(1);
+    images
+        .flat_map(|image| image.pixels().step_by(step))
+        .map(|p| [p[0], p[1], p[2]])
+        .collect()
+}
+
+///
+/// Pixels taken evenly from images as they come, at most `MAX_SAMPLES` of them.
+/// When it fills up every other sample is dropped and later images are sampled
+/// half as often, so the first images do not outweigh the last.
+#[derive(Default)]
+pub struct PixelSampler {
+    samples: Vec<[u8; 3]>,
+    /// Times the samples were halved, each image gives every `1 << halved`th
+    /// pixel
+    halved: u32,
+}
+
+impl PixelSampler {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add(&mut self, image: &RgbaImage) {
+        let pixels = (image.width() * image.height()) as usize;
+        while self.samples.len() + pixels.div_ceil(1 << self.halved) > MAX_SAMPLES {
+            self.samples = self.samples.iter().step_by(2).copied().collect();
+            self.halved += 1;
+        }
+        let step = 1 << self.halved;
+        self.samples
+            .extend(image.pixels().step_by(step).map(|p| [p[0], p[1], p[2]]));
+    }
+
+    pub fn into_samples(self) -> Vec<[u8; 3]> {
+        self.samples
+    }
+}
+
+fn widest_channel(pixels: &[[u8; 3]]) -> (usize, u8) {
+    (0..3)
+        .map(|c| {
+            let min = pixels.iter().map(|p| p[c]).min().unwrap_or(0);
+            let max = pixels.iter().map(|p| p[c]).max().unwrap_or(0);
+            (c, max - min)
+        })
+        .max_by_key(|(_, range)| *range)
+        .unwrap()
+}
+
+#[cfg(test)]
+mod palette_test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn median_cut_test() {
+        let mut samples = vec![[250, 10, 10]; 100];
+        samples.extend(vec![[10, 10, 240]; 50]);
+        samples.extend(vec![[12, 12, 244]; 50]);
+        let mut palette = Palette::median_cut(samples.clone(), 2);
+        assert_eq!(palette.len(), 2);
+        let red = palette.nearest([255, 0, 0]) as usize;
+        let blue = palette.nearest([0, 0, 255]) as usize;
+        assert_ne!(red, blue);
+        assert_eq!(palette.colors[red], [250, 10, 10]);
+        assert_eq!(palette.colors[blue], [11, 11, 242]);
+
+        // never more colours than there are
+        assert_eq!(Palette::median_cut(samples, 256).len(), 3);
+    }
+
+    #[test]
+    fn pixel_sampler_test() {
+        let mut sampler = PixelSampler::new();
+        for i in 0..40u32 {
+            let shade = if i < 20 { 0 } else { 255 };
+            sampler.add(&RgbaImage::from_pixel(64, 64, Rgba([shade, 0, 0, 0xff])));
+        }
+        let samples = sampler.into_samples();
+        assert!(samples.len() <= MAX_SAMPLES);
+        // the last frames weigh as much as the first
+        let last = samples.iter().filter(|p| p[0] == 255).count();
+        assert_eq!(last * 2, samples.len());
+    }
+
+    #[test]
+    fn dithering_test() {
+        let image = RgbaImage::from_pixel(16, 16, Rgba([128, 128, 128, 0xff]));
+        let mut palette = Palette::new(vec![[0, 0, 0], [255, 255, 255]]);
+        let white = |indices: &[u8]| indices.iter().filter(|&&i| i == 1).count();
+
+        let plain = palette.index_image(&image, Dithering::None);
+        assert!(white(&plain) == 0 || white(&plain) == 256);
+        // both patterns mix grey from about as much black as white
+        for dithering in [Dithering::FloydSteinberg, Dithering::Ordered] {
+            let dithered = palette.index_image(&image, dithering);
+            assert!(
+                (112..=144).contains(&white(&dithered)),
+                "{:?}: {} white",
+                dithering,
+                white(&dithered)
+            );
+        }
+    }
+}
@@ -0,0 +1,118 @@
+use super::error::CaptureResult;
+
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+///
+/// A file written under a temporary name next to its destination and renamed
+/// into place by `persist`, so the destination never holds a partial file. The
+/// temporary file is removed if it is dropped before.
+pub struct PartialFile {
+    file: Option<BufWriter<File>>,
+    temp: PathBuf,
+    dest: PathBuf,
+}
+
+impl PartialFile {
+    pub fn create(dest: &Path) -> CaptureResult<Self> {
+        let name = dest
+            .file_name()
+            .map(|name| name.to_string_lossy().into_owned())
+            .unwrap_or_default();
+        let temp = dest.with_file_name(format!(".{}.part", name));
+        let file = BufWriter::new(File::create(&temp)?);
+        Ok(PartialFile {
+            file: Some(file),
+            temp,
+            dest: dest.to_path_buf(),
+        })
+    }
+
+    ///
+    /// Flushes the file to disk and renames it to the destination. The
+    /// temporary file is removed if any of that fails.
+    pub fn persist(mut self) -> CaptureResult<PathBuf> {
+        let file = self.file.take().unwrap();
+        let persisted = file
+            .into_inner()
+            .map_err(|e| e.into_error())
+            .and_then(|file| file.sync_all())
+            .and_then(|_| std::fs::rename(&self.temp, &self.dest));
+        if let Err(e) = persisted {
+            let _ = std::fs::remove_file(&self.temp);
+            return Err(e.into());
+        }
+        Ok(self.dest.clone())
+    }
+}
+
+impl Write for PartialFile {
+    fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+        self.file.as_mut().unwrap().write(buf)
+    }
+
+    fn flush(&mut self) -> std::io::Result<()> {
+        self.file.as_mut().unwrap().flush()
+    }
+}
+
+impl Seek for PartialFile {
+    fn seek(&mut self, pos: SeekFrom) -> std::io::Result<u64> {
+        self.file.as_mut().unwrap().seek(pos)
+    }
+}
+
+impl Drop for PartialFile {
+    fn drop(&mut self) {
+        if self.file.take().is_some() {
+            if let Err(e) = std::fs::remove_file(&self.temp) {
+                log::warn!("failed to remove {}: {}", self.temp.display(), e);
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod partial_file_test {
+    use super::*;
+
+    #[test]
+    fn persist_and_drop_test() {
+        let dir = std::env::temp_dir().join(format!("polaroid-partial-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("CAP.gif");
+
+        let mut file = PartialFile::create(&dest).unwrap();
+        file.write_all(b"GIF89a").unwrap();
+        assert!(!dest.exists());
+        drop(file);
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        let mut file = PartialFile::create(&dest).unwrap();
+        file.write_all(b"GIF89a").unwrap();
+        assert_eq!(file.persist().unwrap(), dest);
+        assert_eq!(std::fs::read(&dest).unwrap(), b"GIF89a");
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 1);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+
+    /// A full disk fails the flush, which still removes the temporary file
+    #[cfg(target_os = "linux")]
+    #[test]
+    fn failed_persist_test() {
+        let dir = std::env::temp_dir().join(format!("polaroid-full-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let dest = dir.join("CAP.gif");
+
+        let mut file = PartialFile::create(&dest).unwrap();
+        let full = File::options().write(true).open("/dev/full").unwrap();
+        file.file = Some(BufWriter::new(full));
+        file.write_all(b"GIF89a").unwrap();
+        assert!(file.persist().is_err());
+        assert_eq!(std::fs::read_dir(&dir).unwrap().count(), 0);
+
+        std::fs::remove_dir_all(&dir).unwrap();
+    }
+}
@@ -0,0 +1,117 @@
+//! Progress of a running recording, reported to the application while the
+//! capture and encode tasks are at work.
+
+use super::error::CaptureError;
+
+use std::path::PathBuf;
+use std::sync::atomic::{AtomicUsize, Ordering};
+use std::sync::Arc;
+use std::time::Duration;
+
+/// A snapshot of a running recording
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Default)]
+pub struct RecordingProgress {
+    pub frames_captured: usize,
+    /// Ticks missed because capturing was too slow, and unchanged frames merged
+    /// into the previous one
+    pub frames_dropped: usize,
+    /// Frames waiting for the encoder
+    pub queued: usize,
+    /// Recorded time, without pauses
+    pub elapsed: Duration,
+    /// Size of the file if the recording was stopped now
+    pub estimated_size: usize,
+}
+
+#[derive(Debug, Clone)]
+pub enum CaptureProgress {
+    Recording(RecordingProgress),
+    /// The capture was written to the path
+    Finished(PathBuf),
+    /// The capture meant for the path failed
+    Failed(PathBuf, Arc<CaptureError>),
+}
+
+///
+/// Counters shared by the capture and the encode task of a recording
+#[derive(Debug, Default)]
+pub struct RecordingCounters {
+    captured: AtomicUsize,
+    missed: AtomicUsize,
+    merged: AtomicUsize,
+    encoded: AtomicUsize,
+    bytes: AtomicUsize,
+}
+
+impl RecordingCounters {
+    pub fn new() -> Self {
+        Self::default()
+    }
+
+    pub fn add_captured(&self) {
+        self.captured.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_missed(&self, ticks: usize) {
+        self.missed.fetch_add(ticks, Ordering::Relaxed);
+    }
+
+    pub fn add_merged(&self) {
+        self.merged.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_encoded(&self) {
+        self.encoded.fetch_add(1, Ordering::Relaxed);
+    }
+
+    pub fn add_bytes(&self, bytes: usize) {
+        self.bytes.fetch_add(bytes, Ordering::Relaxed);
+    }
+
+    ///
+    /// The progress so far. The queued frames are estimated at the average size
+    /// of the frames encoded before them.
+    pub fn snapshot(&self, elapsed: Duration) -> RecordingProgress {
+        let captured = self.captured.load(Ordering::Relaxed);
+        let merged = self.merged.load(Ordering::Relaxed);
+        let encoded = self.encoded.load(Ordering::Relaxed);
+        let bytes = self.bytes.load(Ordering::Relaxed);
+        let queued = captured.saturating_sub(encoded + merged);
+        let estimated_size = match encoded {
+            0 => bytes,
+            _ => bytes + bytes / encoded * queued,
+        };
+        RecordingProgress {
+            frames_captured: captured,
+            frames_dropped: self.missed.load(Ordering::Relaxed) + merged,
+            queued,
+            elapsed,
+            estimated_size,
+        }
+    }
+}
+
+#[cfg(test)]
+mod progress_test {
+    use super::*;
+
+    #[test]
+    fn snapshot_test() {
+        let counters = RecordingCounters::new();
+        for _ in 0..10 {
+            counters.add_captured();
+        }
+        counters.add_missed(2);
+        counters.add_merged();
+        for _ in 0..4 {
+            counters.add_encoded();
+        }
+        counters.add_bytes(4000);
+
+        let progress = counters.snapshot(Duration::from_secs(1));
+        assert_eq!(progress.frames_captured, 10);
+        assert_eq!(progress.frames_dropped, 3);
+        assert_eq!(progress.queued, 5);
+        assert_eq!(progress.estimated_size, 4000 + 5 * 1000);
+    }
+}
@@ -0,0 +1,162 @@
+//! Instant replay keeps the last seconds of a region in memory, so a recording
+//! can be saved after the fact.
+
+use super::capture::TimedFrame;
+use super::config::ReplayConfig;
+use super::frame_buffer::FramePool;
+
+use image::RgbaImage;
+use std::collections::VecDeque;
+use std::time::Duration;
+
+/// A buffered frame, deflated when the replay is compressed
+struct StoredFrame {
+    timestamp: Duration,
+    width: u32,
+    height: u32,
+    data: Vec<u8>,
+    compressed: bool,
+}
+
+///
+/// Ring buffer of the most recent frames. Frames older than the configured
+/// duration are dropped, and so are the oldest ones while the buffer is over its
+/// memory limit.
+pub struct ReplayBuffer {
+    frames: VecDeque<StoredFrame>,
+    bytes: usize,
+    max_age: Duration,
+    memory_limit: usize,
+    compress: bool,
+}
+
+impl ReplayBuffer {
+    pub fn new(config: &ReplayConfig) -> Self {
+        ReplayBuffer {
+            frames: VecDeque::new(),
+            bytes: 0,
+            max_age: Duration::from_secs_f64(config.seconds),
+            memory_limit: config.memory_limit,
+            compress: config.compress,
+        }
+    }
+
+    ///
+    /// Adds the newest frame. With compression the frame buffer goes back to
+    /// `pool` once deflated.
+    pub fn push(&mut self, frame: TimedFrame, pool: &FramePool) {
+        let (width, height) = frame.image.dimensions();
+        let data = if self.compress {
+            let raw = frame.image.into_raw();
+            let data = miniz_oxide::deflate::compress_to_vec(&raw, 1);
+            pool.recycle(raw);
+            data
+        } else {
+            frame.image.into_raw()
+        };
+        self.bytes += data.len();
+        self.frames.push_back(StoredFrame {
+            timestamp: frame.timestamp,
+            width,
+            height,
+            data,
+            compressed: self.compress,
+        });
+
+        let newest = frame.timestamp;
+        while self.frames.len() > 1 {
+            let oldest = &self.frames[0];
+            if newest - oldest.timestamp <= self.max_age && self.bytes <= self.memory_limit {
+                break;
+            }
+            self.bytes -= oldest.data.len();
+            self.frames.pop_front();
+        }
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames.len()
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames.is_empty()
+    }
+
+    ///
+    /// Memory held by the buffered frames
+    pub fn bytes(&self) -> usize {
+        self.bytes
+    }
+
+    pub fn clear(&mut self) {
+        self.frames.clear();
+        self.bytes = 0;
+    }
+
+    ///
+    /// Copies out the buffered frames, oldest first
+    pub fn frames(&self) -> Vec<TimedFrame> {
+        self.frames
+            .iter()
+            .filter_map(|stored| {
+                let data = if stored.compressed {
+                    miniz_oxide::inflate::decompress_to_vec(&stored.data).ok()?
+                } else {
+                    stored.data.clone()
+                };
+                Some(TimedFrame {
+                    image: RgbaImage::from_raw(stored.width, stored.height, data)?,
+                    timestamp: stored.timestamp,
+                })
+            })
+            .collect()
+    }
+}
+
+#[cfg(test)]
+mod replay_test {
+    use super::*;
+    use image::Rgba;
+
+    fn frame(ms: u64, value: u8) -> TimedFrame {
+        TimedFrame {
+            image: RgbaImage::from_pixel(8, 8, Rgba([value, 0, 0, 0xff])),
+            timestamp: Duration::from_millis(ms),
+        }
+    }
+
+    #[test]
+    fn replay_drops_old_frames_test() {
+        let pool = FramePool::new();
+        let mut replay = ReplayBuffer::new(&ReplayConfig {
+            seconds: 1.0,
+            compress: true,
+            ..ReplayConfig::default()
+        });
+        for i in 0..30 {
+            replay.push(frame(i * 100, i as u8), &pool);
+        }
+        // frames from 1.9s to 2.9s
+        let frames = replay.frames();
+        assert_eq!(frames.len(), 11);
+        assert_eq!(frames[0].timestamp, Duration::from_millis(1900));
+        assert_eq!(frames[0].image.get_pixel(0, 0).0, [19, 0, 0, 0xff]);
+        assert!(replay.bytes() < 11 * 8 * 8 * 4);
+    }
+
+    #[test]
+    fn replay_memory_limit_test() {
+        let pool = FramePool::new();
+        let mut replay = ReplayBuffer::new(&ReplayConfig {
+            seconds: 10.0,
+            memory_limit: 3 * 8 * 8 * 4,
+            compress: false,
+            ..ReplayConfig::default()
+        });
+        for i in 0..10 {
+            replay.push(frame(i * 100, i as u8), &pool);
+        }
+        assert_eq!(replay.len(), 3);
+        assert_eq!(replay.frames()[0].timestamp, Duration::from_millis(700));
+    }
+}
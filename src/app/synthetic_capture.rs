@@ -0,0 +1,247 @@
+use super::canvas::Bound2;
+use super::capture_backend::{CaptureBackend, PixelFormat};
+use super::error::{CaptureError, CaptureResult};
+
+use image::{
+    codecs::gif::GifDecoder, imageops, AnimationDecoder, ImageError, ImageResult, Rgba, RgbaImage,
+};
+
+use std::fs::File;
+use std::io::BufReader;
+use std::path::PathBuf;
+
+/// Where the frames of a `SyntheticCaptureBackend` come from
+#[derive(Debug, Clone, PartialEq, Eq)]
+#[allow(unused)]
+pub enum SyntheticSource {
+    /// Every PNG in the directory in file name order, looping at the end
+    Directory(PathBuf),
+    /// The frames of an existing GIF, looping at the end
+    Gif(PathBuf),
+    /// A white vertical bar moving 4 pixels to the right each frame
+    MovingBar,
+    /// The index of the frame drawn as digits
+    FrameCounter,
+}
+
+///
+/// A backend that never touches the screen. File sources stand in for the whole
+/// desktop and the opened region is cropped out of them, while the generated
+/// patterns are drawn directly at the size of the region.
+pub struct SyntheticCaptureBackend {
+    source: SyntheticSource,
+    files: Vec<PathBuf>,
+    frames: Vec<RgbaImage>,
+    bound: Bound2,
+    frame_index: usize,
+}
+
+impl SyntheticCaptureBackend {
+    pub fn new(source: SyntheticSource) -> ImageResult<Self> {
+        let mut files = Vec::new();
+        let mut frames = Vec::new();
+        match &source {
+            SyntheticSource::Directory(dir) => {
+                for entry in std::fs::read_dir(dir)? {
+                    let path = entry?.path();
+                    let is_png = path
+                        .extension()
+                        .is_some_and(|ext| ext.eq_ignore_ascii_case("png"));
+                    if is_png {
+                        files.push(path);
+                    }
+                }
+                files.sort();
+                if files.is_empty() {
+                    return Err(ImageError::IoError(std::io::Error::new(
+                        std::io::ErrorKind::NotFound,
+                        format!("no png files in {}", dir.display()),
+                    )));
+                }
+            }
+            SyntheticSource::Gif(path) => {
+                let decoder = GifDecoder::new(BufReader::new(File::open(path)?))?;
+                frames = decoder
+                    .into_frames()
+                    .map(|frame| frame.map(|f| f.into_buffer()))
+                    .collect::<ImageResult<_>>()?;
+            }
+            SyntheticSource::MovingBar | SyntheticSource::FrameCounter => (),
+        }
+        Ok(SyntheticCaptureBackend {
+            source,
+            files,
+            frames,
+            bound: Bound2::default(),
+            frame_index: 0,
+        })
+    }
+
+    ///
+    /// Number of frames produced since the region was opened
+    #[allow(unused)]
+    pub fn frame_index(&self) -> usize {
+        self.frame_index
+    }
+
+    fn next_image(&mut self) -> CaptureResult<RgbaImage> {
+        let (width, height) = self.size();
+        let index = self.frame_index;
+        Ok(match &self.source {
+            SyntheticSource::Directory(_) => {
+                let path = &self.files[index % self.files.len()];
+                let desktop = image::open(path).map_err(|e| {
+                    CaptureError::Backend(format!("failed to load {}: {}", path.display(), e))
+                })?;
+                crop_region(&desktop.into_rgba8(), self.bound)
+            }
+            SyntheticSource::Gif(_) => {
+                crop_region(&self.frames[index % self.frames.len()], self.bound)
+            }
+            SyntheticSource::MovingBar => moving_bar(width, height, index),
+            SyntheticSource::FrameCounter => frame_counter(width, height, index),
+        })
+    }
+}
+
+impl CaptureBackend for SyntheticCaptureBackend {
+    fn name(&self) -> &'static str {
+        "synthetic"
+    }
+
+    fn open(&mut self, region: Bound2) -> CaptureResult<()> {
+        self.bound = region;
+        self.frame_index = 0;
+        Ok(())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.bound.get_width(), self.bound.get_height())
+    }
+
+    fn frame_bound(&mut self) -> Bound2 {
+        self.bound
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Rgba8
+    }
+
+    /// The pointer rests in the middle of the region
+    fn cursor_position(&mut self) -> Option<(i32, i32)> {
+        Some((
+            (self.bound.min.0 + self.bound.max.0) / 2,
+            (self.bound.min.1 + self.bound.max.1) / 2,
+        ))
+    }
+
+    fn grab_frame(&mut self, buf: &mut Vec<u8>) -> CaptureResult<()> {
+        let img = self.next_image()?;
+        self.frame_index += 1;
+        buf.clear();
+        buf.extend_from_slice(img.as_raw());
+        Ok(())
+    }
+}
+
+///
+/// Copies `bound` out of `desktop`, parts outside of it are left black
+fn crop_region(desktop: &RgbaImage, bound: Bound2) -> RgbaImage {
+    let mut img =
+        RgbaImage::from_pixel(bound.get_width(), bound.get_height(), Rgba([0, 0, 0, 0xff]));
+    imageops::overlay(&mut img, desktop, -bound.min.0 as i64, -bound.min.1 as i64);
+    img
+}
+
+fn moving_bar(width: u32, height: u32, index: usize) -> RgbaImage {
+    const BAR_WIDTH: u32 = 8;
+    let mut img = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 0xff]));
+    if width == 0 {
+        return img;
+    }
+    let start = (index as u32 * 4) % width;
+    for x in start..(start + BAR_WIDTH).min(width) {
+        for y in 0..height {
+            img.put_pixel(x, y, Rgba([0xff, 0xff, 0xff, 0xff]));
+        }
+    }
+    img
+}
+
+/// 3x5 bitmaps of the digits, one row per 3 bits
+const DIGITS: [[u8; 5]; 10] = [
+    [0b111, 0b101, 0b101, 0b101, 0b111],
+    [0b010, 0b110, 0b010, 0b010, 0b111],
+    [0b111, 0b001, 0b111, 0b100, 0b111],
+    [0b111, 0b001, 0b111, 0b001, 0b111],
+    [0b101, 0b101, 0b111, 0b001, 0b001],
+    [0b111, 0b100, 0b111, 0b001, 0b111],
+    [0b111, 0b100, 0b111, 0b101, 0b111],
+    [0b111, 0b001, 0b001, 0b001, 0b001],
+    [0b111, 0b101, 0b111, 0b101, 0b111],
+    [0b111, 0b101, 0b111, 0b001, 0b111],
+];
+
+fn frame_counter(width: u32, height: u32, index: usize) -> RgbaImage {
+    let mut img = RgbaImage::from_pixel(width, height, Rgba([0, 0, 0, 0xff]));
+    let text = index.to_string();
+    // each digit takes 4 columns including the gap, plus a 1 cell margin
+    let cols = text.len() as u32 * 4 + 1;
+    let scale = std::cmp::min(width / cols, height / 7).max(1);
+    for (i, digit) in text.bytes().enumerate() {
+        let glyph = DIGITS[(digit - b'0') as usize];
+        for (row, bits) in glyph.iter().enumerate() {
+            for col in 0..3 {
+                if bits & (0b100 >> col) == 0 {
+                    continue;
+                }
+                let x0 = (1 + i as u32 * 4 + col) * scale;
+                let y0 = (1 + row as u32) * scale;
+                for y in y0..(y0 + scale).min(height) {
+                    for x in x0..(x0 + scale).min(width) {
+                        img.put_pixel(x, y, Rgba([0xff, 0xff, 0xff, 0xff]));
+                    }
+                }
+            }
+        }
+    }
+    img
+}
+
+#[cfg(test)]
+mod synthetic_capture_test {
+    use super::*;
+
+    #[test]
+    fn moving_bar_advances_test() {
+        let mut backend = SyntheticCaptureBackend::new(SyntheticSource::MovingBar).unwrap();
+        backend.open(Bound2::new((0, 0), (32, 4))).unwrap();
+        let first = backend.capture_image().unwrap();
+        let second = backend.capture_image().unwrap();
+        assert_eq!(first.get_pixel(0, 0).0, [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(second.get_pixel(0, 0).0, [0, 0, 0, 0xff]);
+        assert_eq!(second.get_pixel(4, 0).0, [0xff, 0xff, 0xff, 0xff]);
+        assert_eq!(backend.frame_index(), 2);
+    }
+
+    #[test]
+    fn directory_source_crops_region_test() {
+        let dir = std::env::temp_dir().join(format!("polaroid-synthetic-{}", std::process::id()));
+        std::fs::create_dir_all(&dir).unwrap();
+        let mut desktop = RgbaImage::from_pixel(16, 16, Rgba([0, 0, 0xff, 0xff]));
+        desktop.put_pixel(4, 4, Rgba([0xff, 0, 0, 0xff]));
+        desktop.save(dir.join("0.png")).unwrap();
+
+        let mut backend =
+            SyntheticCaptureBackend::new(SyntheticSource::Directory(dir.clone())).unwrap();
+        backend.open(Bound2::new((4, 4), (8, 8))).unwrap();
+        let img = backend.capture_image().unwrap();
+        std::fs::remove_dir_all(&dir).unwrap();
+        // a frame that cannot be read fails instead of coming back black
+        assert!(backend.capture_image().is_err());
+
+        assert_eq!(img.dimensions(), (4, 4));
+        assert_eq!(img.get_pixel(0, 0).0, [0xff, 0, 0, 0xff]);
+        assert_eq!(img.get_pixel(1, 1).0, [0, 0, 0xff, 0xff]);
+    }
+}
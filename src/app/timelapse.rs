@@ -0,0 +1,135 @@
+//! Timelapse frames are taken minutes apart over hours, far too many to keep in
+//! memory, so they are written to disk as they come in and only read back when
+//! the timelapse is assembled.
+
+use super::capture::{self, TimedFrame};
+use super::config::{AnimationFormat, CaptureConfig};
+use super::error::CaptureResult;
+use super::frame_buffer::FramePool;
+use super::progress::RecordingCounters;
+
+use image::codecs::png::{CompressionType, FilterType, PngEncoder};
+use image::{ColorType, ImageEncoder, RgbaImage};
+use std::fs::File;
+use std::io::{BufWriter, Seek, Write};
+use std::path::{Path, PathBuf};
+use std::time::Duration;
+
+///
+/// Directory of numbered PNG frames, removed when the store is dropped
+pub struct TimelapseStore {
+    dir: PathBuf,
+    frames: usize,
+}
+
+impl TimelapseStore {
+    ///
+    /// Creates a fresh directory for the frames below `parent`
+    pub fn create(parent: &Path) -> CaptureResult<Self> {
+        let stamp = std::time::SystemTime::now()
+            .duration_since(std::time::UNIX_EPOCH)
+            .unwrap_or_default()
+            .as_millis();
+        let dir = parent.join(format!(
+            "polaroid-timelapse-{}-{}",
+            std::process::id(),
+            stamp
+        ));
+        std::fs::create_dir_all(&dir)?;
+        Ok(TimelapseStore { dir, frames: 0 })
+    }
+
+    pub fn len(&self) -> usize {
+        self.frames
+    }
+
+    pub fn is_empty(&self) -> bool {
+        self.frames == 0
+    }
+
+    fn frame_path(&self, index: usize) -> PathBuf {
+        self.dir.join(format!("{:08}.png", index))
+    }
+
+    ///
+    /// Appends a frame. Compression is kept fast, a frame is written while the
+    /// next one is due.
+    pub fn push(&mut self, image: &RgbaImage) -> CaptureResult<()> {
+        let file = BufWriter::new(File::create(self.frame_path(self.frames))?);
+        PngEncoder::new_with_quality(file, CompressionType::Fast, FilterType::Sub).write_image(
+            image.as_raw(),
+            image.width(),
+            image.height(),
+            ColorType::Rgba8,
+        )?;
+        self.frames += 1;
+        Ok(())
+    }
+
+    ///
+    /// Reads the frames back in the order they were taken
+    pub fn frames(&self) -> impl Iterator<Item = CaptureResult<RgbaImage>> + '_ {
+        (0..self.frames).map(move |index| Ok(image::open(self.frame_path(index))?.into_rgba8()))
+    }
+
+    ///
+    /// Assembles the frames into an animation of `format` written to `out`,
+    /// played back at `playback_fps`
+    pub fn encode<W: Write + Seek>(
+        &self,
+        out: W,
+        format: AnimationFormat,
+        playback_fps: u32,
+        config: &CaptureConfig,
+        pool: &FramePool,
+    ) -> CaptureResult<()> {
+        let interval = Duration::from_secs_f64(1.0 / playback_fps as f64);
+        let mut error = None;
+        let frames = self
+            .frames()
+            .map_while(|frame| frame.map_err(|e| error = Some(e)).ok())
+            .enumerate()
+            .map(|(i, image)| TimedFrame {
+                image,
+                timestamp: interval * i as u32,
+            });
+        let counters = RecordingCounters::new();
+        let encoded =
+            capture::encode_animation(out, format, frames, interval, config, pool, &counters);
+        match error {
+            Some(e) => Err(e),
+            None => encoded,
+        }
+    }
+}
+
+impl Drop for TimelapseStore {
+    fn drop(&mut self) {
+        if let Err(e) = std::fs::remove_dir_all(&self.dir) {
+            log::warn!("failed to remove {}: {}", self.dir.display(), e);
+        }
+    }
+}
+
+#[cfg(test)]
+mod timelapse_test {
+    use super::*;
+    use image::Rgba;
+
+    #[test]
+    fn store_round_trip_test() {
+        let mut store = TimelapseStore::create(&std::env::temp_dir()).unwrap();
+        for i in 0..3u8 {
+            store
+                .push(&RgbaImage::from_pixel(8, 4, Rgba([i, 0, 0, 0xff])))
+                .unwrap();
+        }
+        assert_eq!(store.len(), 3);
+        let frames: Vec<_> = store.frames().map(|f| f.unwrap()).collect();
+        assert_eq!(frames[2].get_pixel(7, 3).0, [2, 0, 0, 0xff]);
+
+        let dir = store.dir.clone();
+        drop(store);
+        assert!(!dir.exists());
+    }
+}
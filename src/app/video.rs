@@ -0,0 +1,476 @@
+//! MP4 and WebM recordings, encoded by a local ffmpeg.
+//!
+//! Raw frames would reach ffmpeg without their timestamps, so they are piped in
+//! a bare Matroska stream of uncompressed RGBA frames, each with the time it was
+//! captured and how long it is shown. ffmpeg writes the video to its stdout,
+//! which is read on a thread of its own so that neither pipe fills up and stalls
+//! the other.
+
+use super::capture::FrameWriter;
+use super::config::{AnimationFormat, VideoConfig};
+use super::error::{CaptureError, CaptureResult};
+use super::frame_buffer::FramePool;
+
+use image::RgbaImage;
+use std::io::{Read, Write};
+use std::path::Path;
+use std::process::{Child, ChildStdin, Command, Stdio};
+use std::sync::mpsc::{self, Receiver};
+use std::thread::JoinHandle;
+use std::time::Duration;
+
+const EBML: [u8; 4] = [0x1a, 0x45, 0xdf, 0xa3];
+const SEGMENT: [u8; 4] = [0x18, 0x53, 0x80, 0x67];
+const INFO: [u8; 4] = [0x15, 0x49, 0xa9, 0x66];
+const TRACKS: [u8; 4] = [0x16, 0x54, 0xae, 0x6b];
+const CLUSTER: [u8; 4] = [0x1f, 0x43, 0xb6, 0x75];
+const TIMESTAMP: [u8; 1] = [0xe7];
+const BLOCK_GROUP: [u8; 1] = [0xa0];
+const BLOCK: [u8; 1] = [0xa1];
+const BLOCK_DURATION: [u8; 1] = [0x9b];
+
+/// Size of an element that runs to the end of the stream
+const UNKNOWN_SIZE: [u8; 8] = [0x01, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff, 0xff];
+
+///
+/// Element sizes are always written in 8 bytes, which leaves them the same
+/// length whatever the size
+fn size(size: usize) -> [u8; 8] {
+    let mut bytes = (size as u64).to_be_bytes();
+    bytes[0] = 0x01;
+    bytes
+}
+
+fn element(id: &[u8], body: &[u8]) -> Vec<u8> {
+    let mut data = id.to_vec();
+    data.extend_from_slice(&size(body.len()));
+    data.extend_from_slice(body);
+    data
+}
+
+fn uint(id: &[u8], value: u64) -> Vec<u8> {
+    let bytes = value.to_be_bytes();
+    let skip = bytes.iter().take(7).take_while(|&&b| b == 0).count();
+    element(id, &bytes[skip..])
+}
+
+///
+/// The stream header up to the first frame, for a single track of
+/// uncompressed RGBA frames of `width` by `height` with millisecond timestamps
+fn stream_header(width: u32, height: u32) -> Vec<u8> {
+    let mut header = element(
+        &EBML,
+        &[
+            uint(&[0x42, 0x86], 1),
+            uint(&[0x42, 0xf7], 1),
+            uint(&[0x42, 0xf2], 4),
+            uint(&[0x42, 0xf3], 8),
+            element(&[0x42, 0x82], b"matroska"),
+            uint(&[0x42, 0x87], 4),
+            uint(&[0x42, 0x85], 2),
+        ]
+        .concat(),
+    );
+    header.extend_from_slice(&SEGMENT);
+    header.extend_from_slice(&UNKNOWN_SIZE);
+    let info = [
+        uint(&[0x2a, 0xd7, 0xb1], 1_000_000),
+        element(&[0x4d, 0x80], b"polaroid"),
+        element(&[0x57, 0x41], b"polaroid"),
+    ]
+    .concat();
+    header.extend(element(&INFO, &info));
+    let video = [
+        uint(&[0xb0], width as u64),
+        uint(&[0xba], height as u64),
+        element(&[0x2e, 0xb5, 0x24], b"RGBA"),
+    ]
+    .concat();
+    let track = [
+        uint(&[0xd7], 1),
+        uint(&[0x73, 0xc5], 1),
+        uint(&[0x83], 1),
+        element(&[0x86], b"V_UNCOMPRESSED"),
+        element(&[0xe0], &video),
+    ]
+    .concat();
+    header.extend(element(&TRACKS, &element(&[0xae], &track)));
+    header
+}
+
+///
+/// Looks for ffmpeg at `ffmpeg`, telling that it is missing apart from other
+/// failures to run it
+pub fn check_ffmpeg(ffmpeg: &Path) -> CaptureResult<()> {
+    Command::new(ffmpeg)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stdout(Stdio::null())
+        .stderr(Stdio::null())
+        .status()
+        .map(|_| ())
+        .map_err(|e| spawn_error(ffmpeg, e))
+}
+
+///
+/// The option asking ffmpeg to keep the frame timestamps, which it would
+/// otherwise resample to a constant rate. ffmpeg before 5.1 only knows `-vsync`.
+fn fps_mode_option(ffmpeg: &Path) -> &'static str {
+    let version = Command::new(ffmpeg)
+        .arg("-version")
+        .stdin(Stdio::null())
+        .stderr(Stdio::null())
+        .output()
+        .map(|output| String::from_utf8_lossy(&output.stdout).into_owned())
+        .unwrap_or_default();
+    match parse_version(&version) {
+        Some(version) if version < (5, 1) => "-vsync",
+        _ => "-fps_mode",
+    }
+}
+
+///
+/// The release of `ffmpeg -version`, `None` for builds from git
+fn parse_version(version: &str) -> Option<(u32, u32)> {
+    let release = version.strip_prefix("ffmpeg version ")?;
+    let release = release.strip_prefix('n').unwrap_or(release);
+    let mut numbers = release
+        .split(|c: char| !c.is_ascii_digit())
+        .map(|n| n.parse().ok());
+    Some((numbers.next()??, numbers.next().flatten().unwrap_or(0)))
+}
+
+fn spawn_error(ffmpeg: &Path, e: std::io::Error) -> CaptureError {
+    match e.kind() {
+        std::io::ErrorKind::NotFound => CaptureError::EncoderNotFound(ffmpeg.display().to_string()),
+        _ => e.into(),
+    }
+}
+
+///
+/// The ffmpeg arguments encoding the piped stream into `format`, written to
+/// stdout
+fn ffmpeg_args(format: AnimationFormat, config: &VideoConfig, fps_mode: &str) -> Vec<String> {
+    let mut args: Vec<String> = ["-hide_banner", "-loglevel", "error", "-f", "matroska"]
+        .iter()
+        .chain(&["-i", "pipe:0", "-an"])
+        .map(|s| s.to_string())
+        .collect();
+    let (preset, muxer): (_, &[&str]) = match format {
+        // MP4 moves its index to the front once done, which needs a seekable
+        // file, fragments do without
+        AnimationFormat::Mp4 => (
+            &config.mp4,
+            &[
+                "-movflags",
+                "frag_keyframe+empty_moov+default_base_moof",
+                "-f",
+                "mp4",
+            ],
+        ),
+        _ => (&config.webm, &["-f", "webm"]),
+    };
+    // frames last as long as the recording held them
+    args.extend([fps_mode.to_string(), "vfr".to_string()]);
+    args.extend(["-c:v".to_string(), preset.codec.clone()]);
+    args.extend(["-crf".to_string(), preset.crf.to_string()]);
+    args.extend(preset.options.iter().cloned());
+    // 4:2:0 chroma wants even sizes
+    args.extend(
+        [
+            "-vf",
+            "pad=ceil(iw/2)*2:ceil(ih/2)*2",
+            "-pix_fmt",
+            "yuv420p",
+        ]
+        .iter()
+        .map(|s| s.to_string()),
+    );
+    args.extend(muxer.iter().map(|s| s.to_string()));
+    args.push("pipe:1".to_string());
+    args
+}
+
+///
+/// Pipes frames into ffmpeg and writes the video it makes to `out`. ffmpeg is
+/// killed if the writer is dropped before `finish`.
+pub struct VideoWriter<W: Write> {
+    out: W,
+    ffmpeg: Child,
+    stdin: Option<ChildStdin>,
+    /// What ffmpeg wrote to stdout, read by a thread
+    output: Receiver<Vec<u8>>,
+    /// What ffmpeg wrote to stderr, once it exited
+    errors: Option<JoinHandle<String>>,
+    /// The size of the frames, once the stream header is written
+    size: Option<(u32, u32)>,
+    timestamp: Duration,
+    pool: FramePool,
+}
+
+impl<W: Write> VideoWriter<W> {
+    pub fn new(
+        out: W,
+        format: AnimationFormat,
+        config: &VideoConfig,
+        pool: FramePool,
+    ) -> CaptureResult<Self> {
+        let mut ffmpeg = Command::new(&config.ffmpeg)
+            .args(ffmpeg_args(format, config, fps_mode_option(&config.ffmpeg)))
+            .stdin(Stdio::piped())
+            .stdout(Stdio::piped())
+            .stderr(Stdio::piped())
+            .spawn()
+            .map_err(|e| spawn_error(&config.ffmpeg, e))?;
+
+        let (tx, output) = mpsc::channel();
+        let mut stdout = ffmpeg.stdout.take().unwrap();
+        std::thread::spawn(move || {
+            let mut buf = vec![0u8; 64 << 10];
+            while let Ok(n) = stdout.read(&mut buf) {
+                if n == 0 || tx.send(buf[..n].to_vec()).is_err() {
+                    break;
+                }
+            }
+        });
+        let mut stderr = ffmpeg.stderr.take().unwrap();
+        let errors = std::thread::spawn(move || {
+            let mut errors = String::new();
+            let _ = stderr.read_to_string(&mut errors);
+            errors
+        });
+
+        Ok(VideoWriter {
+            out,
+            stdin: ffmpeg.stdin.take(),
+            ffmpeg,
+            output,
+            errors: Some(errors),
+            size: None,
+            timestamp: Duration::ZERO,
+            pool,
+        })
+    }
+
+    fn write_output(&mut self) -> std::io::Result<()> {
+        for data in self.output.try_iter() {
+            self.out.write_all(&data)?;
+        }
+        Ok(())
+    }
+
+    ///
+    /// Waits for ffmpeg to exit, turning a failure into an error with what it
+    /// wrote to stderr
+    fn wait(&mut self) -> CaptureResult<()> {
+        self.stdin = None;
+        let status = self.ffmpeg.wait()?;
+        let errors = self
+            .errors
+            .take()
+            .and_then(|errors| errors.join().ok())
+            .unwrap_or_default();
+        match status.success() {
+            true => Ok(()),
+            false => Err(CaptureError::Encoding(
+                format!("ffmpeg failed with {}: {}", status, errors.trim()).into(),
+            )),
+        }
+    }
+
+    fn write_stream(&mut self, data: &[u8]) -> CaptureResult<()> {
+        let written = self.stdin.as_mut().unwrap().write_all(data);
+        if let Err(e) = written {
+            // ffmpeg stopped reading, the reason is on its stderr
+            self.wait()?;
+            return Err(e.into());
+        }
+        Ok(())
+    }
+}
+
+impl<W: Write> FrameWriter for VideoWriter<W> {
+    fn write_frame(&mut self, image: RgbaImage, delay: Duration) -> CaptureResult<()> {
+        let dimensions = image.dimensions();
+        match self.size {
+            None => {
+                self.write_stream(&stream_header(dimensions.0, dimensions.1))?;
+                self.size = Some(dimensions);
+            }
+            Some(size) if size != dimensions => {
+                return Err(CaptureError::InvalidParameter(format!(
+                    "frame size changed from {:?} to {:?}",
+                    size, dimensions
+                )));
+            }
+            Some(_) => {}
+        }
+
+        // track 1, at the cluster timestamp, no flags
+        let block_header = [0x81, 0, 0, 0];
+        let block_size = block_header.len() + image.as_raw().len();
+        let duration = uint(&BLOCK_DURATION, delay.as_millis() as u64);
+        let group_size = BLOCK.len() + 8 + block_size + duration.len();
+        let timestamp = uint(&TIMESTAMP, self.timestamp.as_millis() as u64);
+        let cluster_size = timestamp.len() + BLOCK_GROUP.len() + 8 + group_size;
+
+        let mut head = CLUSTER.to_vec();
+        head.extend_from_slice(&size(cluster_size));
+        head.extend(timestamp);
+        head.extend_from_slice(&BLOCK_GROUP);
+        head.extend_from_slice(&size(group_size));
+        head.extend_from_slice(&BLOCK);
+        head.extend_from_slice(&size(block_size));
+        head.extend_from_slice(&block_header);
+        self.write_stream(&head)?;
+        self.write_stream(image.as_raw())?;
+        self.write_stream(&duration)?;
+
+        self.timestamp += delay;
+        self.pool.recycle(image.into_raw());
+        self.write_output()?;
+        Ok(())
+    }
+
+    fn finish(mut self) -> CaptureResult<()> {
+        self.wait()?;
+        // the reading thread is done once ffmpeg closed its stdout
+        while let Ok(data) = self.output.recv() {
+            self.out.write_all(&data)?;
+        }
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+impl<W: Write> Drop for VideoWriter<W> {
+    fn drop(&mut self) {
+        if let Ok(None) = self.ffmpeg.try_wait() {
+            let _ = self.ffmpeg.kill();
+            let _ = self.ffmpeg.wait();
+        }
+    }
+}
+
+#[cfg(test)]
+mod video_test {
+    use super::*;
+
+    #[test]
+    fn missing_ffmpeg_test() {
+        let config = VideoConfig {
+            ffmpeg: "/nonexistent/ffmpeg".into(),
+            ..VideoConfig::default()
+        };
+        assert!(matches!(
+            check_ffmpeg(&config.ffmpeg),
+            Err(CaptureError::EncoderNotFound(_))
+        ));
+        assert!(matches!(
+            VideoWriter::new(Vec::new(), AnimationFormat::Mp4, &config, FramePool::new()),
+            Err(CaptureError::EncoderNotFound(_))
+        ));
+    }
+
+    #[test]
+    fn parse_version_test() {
+        let version = "ffmpeg version 4.4.2-0ubuntu0.22.04.1 Copyright (c) 2000-2021";
+        assert_eq!(parse_version(version), Some((4, 4)));
+        assert_eq!(parse_version("ffmpeg version n7.1 Copyright"), Some((7, 1)));
+        assert_eq!(parse_version("ffmpeg version 6 Copyright"), Some((6, 0)));
+        assert_eq!(
+            parse_version("ffmpeg version N-113053-g2ea5f8c Copyright"),
+            None
+        );
+        assert_eq!(parse_version(""), None);
+    }
+
+    #[test]
+    #[ignore = "needs ffmpeg with libx264 and libvpx, e.g. `cargo test -- --ignored`"]
+    fn ffmpeg_encode_test() {
+        let config = VideoConfig::default();
+        for format in [AnimationFormat::Mp4, AnimationFormat::Webm] {
+            let mut data = Vec::new();
+            let mut writer =
+                VideoWriter::new(&mut data, format, &config, FramePool::new()).unwrap();
+            for (i, delay) in [40, 120, 40].iter().enumerate() {
+                // odd sizes are padded for 4:2:0
+                let image = RgbaImage::from_pixel(63, 31, image::Rgba([i as u8 * 80, 0, 0, 0xff]));
+                writer
+                    .write_frame(image, Duration::from_millis(*delay))
+                    .unwrap();
+            }
+            writer.finish().unwrap();
+            match format {
+                AnimationFormat::Mp4 => assert_eq!(&data[4..8], b"ftyp"),
+                _ => assert_eq!(&data[..4], EBML),
+            }
+        }
+    }
+
+    /// Reads an element of the stream, as its id, body and the rest
+    fn read_element(data: &[u8]) -> (Vec<u8>, &[u8], &[u8]) {
+        let id_len = data[0].leading_zeros() as usize + 1;
+        let size_len = data[id_len].leading_zeros() as usize + 1;
+        let mut size = (data[id_len] & 0xffu8.checked_shr(size_len as u32).unwrap_or(0)) as usize;
+        for b in &data[id_len + 1..id_len + size_len] {
+            size = size << 8 | *b as usize;
+        }
+        let body = id_len + size_len;
+        (
+            data[..id_len].to_vec(),
+            &data[body..body + size],
+            &data[body + size..],
+        )
+    }
+
+    fn read_uint(body: &[u8]) -> u64 {
+        body.iter().fold(0, |v, b| v << 8 | *b as u64)
+    }
+
+    /// ffmpeg replaced by `cat`, which hands back the stream it is given
+    #[cfg(unix)]
+    #[test]
+    fn frame_timestamps_test() {
+        use std::os::unix::fs::PermissionsExt;
+
+        let script = std::env::temp_dir().join(format!("polaroid-cat-{}", std::process::id()));
+        std::fs::write(&script, "#!/bin/sh\nexec cat\n").unwrap();
+        std::fs::set_permissions(&script, std::fs::Permissions::from_mode(0o755)).unwrap();
+        let config = VideoConfig {
+            ffmpeg: script.clone(),
+            ..VideoConfig::default()
+        };
+        let mut data = Vec::new();
+        let mut writer =
+            VideoWriter::new(&mut data, AnimationFormat::Webm, &config, FramePool::new()).unwrap();
+        for (i, delay) in [40, 120, 40].iter().enumerate() {
+            let image = RgbaImage::from_pixel(64, 32, image::Rgba([i as u8, 0, 0, 0xff]));
+            writer
+                .write_frame(image, Duration::from_millis(*delay))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        std::fs::remove_file(&script).unwrap();
+
+        let (id, _, rest) = read_element(&data);
+        assert_eq!(id, EBML);
+        assert_eq!(&rest[..4], SEGMENT);
+        let mut rest = &rest[12..];
+        let mut frames = Vec::new();
+        while !rest.is_empty() {
+            let (id, body, next) = read_element(rest);
+            rest = next;
+            if id != CLUSTER {
+                continue;
+            }
+            let (_, timestamp, group) = read_element(body);
+            let (_, group, _) = read_element(group);
+            let (_, block, duration) = read_element(group);
+            let (_, duration, _) = read_element(duration);
+            assert_eq!(block.len(), 4 + 64 * 32 * 4);
+            frames.push((read_uint(timestamp), read_uint(duration), block[4]));
+        }
+        assert_eq!(frames, vec![(0, 40, 0), (40, 120, 1), (160, 40, 2)]);
+    }
+}
@@ -0,0 +1,326 @@
+//! Animated WebP, far smaller than a GIF of the same recording.
+//!
+//! The frames are encoded by libwebp, loaded when a WebP is written so the
+//! other formats work without it. Its simple API encodes still images only, so
+//! the animation container around them is written here, and its size is filled
+//! in once the recording ends.
+
+use super::capture::FrameWriter;
+use super::config::WebpConfig;
+use super::error::{CaptureError, CaptureResult};
+use super::frame_buffer::FramePool;
+use super::frame_delta;
+
+use image::RgbaImage;
+use std::io::{Seek, SeekFrom, Write};
+use std::os::raw::{c_int, c_void};
+use std::time::Duration;
+
+#[cfg(target_os = "windows")]
+const LIBRARY_NAMES: &[&str] = &["libwebp.dll", "webp.dll"];
+#[cfg(target_os = "macos")]
+const LIBRARY_NAMES: &[&str] = &["libwebp.7.dylib", "libwebp.dylib"];
+#[cfg(not(any(target_os = "windows", target_os = "macos")))]
+const LIBRARY_NAMES: &[&str] = &["libwebp.so.7", "libwebp.so"];
+
+type EncodeRgba = unsafe extern "C" fn(*const u8, c_int, c_int, c_int, f32, *mut *mut u8) -> usize;
+type EncodeLosslessRgba =
+    unsafe extern "C" fn(*const u8, c_int, c_int, c_int, *mut *mut u8) -> usize;
+type Free = unsafe extern "C" fn(*mut c_void);
+
+/// `VP8X` flag of an animated file
+const ANIMATION_FLAG: u8 = 0x02;
+/// `ANMF` flag replacing the frame rect instead of blending over it
+const NO_BLEND_FLAG: u8 = 0x02;
+/// Largest value of the 24 bit fields
+const MAX_U24: u32 = 0xff_ffff;
+
+///
+/// The encode functions of libwebp
+pub struct Library {
+    encode_rgba: EncodeRgba,
+    encode_lossless_rgba: EncodeLosslessRgba,
+    free: Free,
+    // keeps the functions above loaded
+    _library: libloading::Library,
+}
+
+impl Library {
+    pub fn load() -> CaptureResult<Self> {
+        let mut error = None;
+        for name in LIBRARY_NAMES {
+            match unsafe { libloading::Library::new(name) } {
+                Ok(library) => return Self::with_library(library).map_err(library_error),
+                Err(e) => error = Some(e),
+            }
+        }
+        Err(library_error(error.unwrap()))
+    }
+
+    fn with_library(library: libloading::Library) -> Result<Self, libloading::Error> {
+        unsafe {
+            Ok(Library {
+                encode_rgba: *library.get::<EncodeRgba>(b"WebPEncodeRGBA\0")?,
+                encode_lossless_rgba: *library
+                    .get::<EncodeLosslessRgba>(b"WebPEncodeLosslessRGBA\0")?,
+                free: *library.get::<Free>(b"WebPFree\0")?,
+                _library: library,
+            })
+        }
+    }
+
+    ///
+    /// Encodes `image` into a still WebP, lossless without `quality`
+    pub fn encode(&self, image: &RgbaImage, quality: Option<f32>) -> CaptureResult<Vec<u8>> {
+        let (width, height) = (image.width() as c_int, image.height() as c_int);
+        let mut output = std::ptr::null_mut();
+        let size = unsafe {
+            match quality {
+                Some(quality) => (self.encode_rgba)(
+                    image.as_ptr(),
+                    width,
+                    height,
+                    width * 4,
+                    quality,
+                    &mut output,
+                ),
+                None => (self.encode_lossless_rgba)(
+                    image.as_ptr(),
+                    width,
+                    height,
+                    width * 4,
+                    &mut output,
+                ),
+            }
+        };
+        if size == 0 || output.is_null() {
+            return Err(CaptureError::Encoding(
+                "libwebp failed to encode a frame".into(),
+            ));
+        }
+        let data = unsafe { std::slice::from_raw_parts(output, size) }.to_vec();
+        unsafe { (self.free)(output as *mut c_void) };
+        Ok(data)
+    }
+}
+
+fn library_error(e: libloading::Error) -> CaptureError {
+    CaptureError::Encoding(format!("libwebp could not be loaded: {}", e).into())
+}
+
+///
+/// The image chunks of a still WebP, which go into an animation frame as they
+/// are. The extended header, if there is one, only describes the still image.
+fn image_chunks(still: &[u8]) -> CaptureResult<&[u8]> {
+    if still.len() < 12 || &still[..4] != b"RIFF" || &still[8..12] != b"WEBP" {
+        return Err(CaptureError::Encoding("libwebp returned no WebP".into()));
+    }
+    let chunks = &still[12..];
+    if chunks.len() >= 8 && &chunks[..4] == b"VP8X" {
+        let size = u32::from_le_bytes([chunks[4], chunks[5], chunks[6], chunks[7]]) as usize;
+        let next = 8 + size + size % 2;
+        return Ok(chunks.get(next..).unwrap_or_default());
+    }
+    Ok(chunks)
+}
+
+fn u24(value: u32) -> [u8; 3] {
+    let bytes = value.min(MAX_U24).to_le_bytes();
+    [bytes[0], bytes[1], bytes[2]]
+}
+
+///
+/// Writes frames into an animated WebP, lossy unless `config.lossless`. With
+/// `config.optimize_frames` the frames after the first are cut to the part that
+/// changed, which replaces that part of the previous frame. WebP frame offsets
+/// are even, so the part may reach a pixel further left and up.
+pub struct WebpWriter<W: Write + Seek> {
+    out: W,
+    library: Library,
+    quality: Option<f32>,
+    optimize: bool,
+    loop_count: u16,
+    /// Where the file starts, once the header is written
+    start: Option<u64>,
+    /// The last frame, when optimizing
+    prev: Option<RgbaImage>,
+    pool: FramePool,
+}
+
+impl<W: Write + Seek> WebpWriter<W> {
+    ///
+    /// `loop_count` is how often the animation plays, 0 loops forever
+    pub fn new(
+        out: W,
+        config: &WebpConfig,
+        loop_count: u16,
+        pool: FramePool,
+    ) -> CaptureResult<Self> {
+        Ok(WebpWriter {
+            out,
+            library: Library::load()?,
+            quality: match config.lossless {
+                true => None,
+                false => Some(config.quality),
+            },
+            optimize: config.optimize_frames,
+            loop_count,
+            start: None,
+            prev: None,
+            pool,
+        })
+    }
+
+    fn write_chunk(&mut self, kind: &[u8; 4], data: &[u8]) -> std::io::Result<()> {
+        self.out.write_all(kind)?;
+        self.out.write_all(&(data.len() as u32).to_le_bytes())?;
+        self.out.write_all(data)?;
+        if data.len() % 2 == 1 {
+            self.out.write_all(&[0])?;
+        }
+        Ok(())
+    }
+
+    fn write_header(&mut self, width: u32, height: u32) -> std::io::Result<()> {
+        self.start = Some(self.out.stream_position()?);
+        // the size is filled in by `finish`
+        self.out.write_all(b"RIFF\0\0\0\0WEBP")?;
+        let mut header = vec![ANIMATION_FLAG, 0, 0, 0];
+        header.extend_from_slice(&u24(width - 1));
+        header.extend_from_slice(&u24(height - 1));
+        self.write_chunk(b"VP8X", &header)?;
+        // white background in BGRA, then the loop count
+        let mut animation = vec![0xff; 4];
+        animation.extend_from_slice(&self.loop_count.to_le_bytes());
+        self.write_chunk(b"ANIM", &animation)
+    }
+}
+
+impl<W: Write + Seek> FrameWriter for WebpWriter<W> {
+    fn write_frame(&mut self, image: RgbaImage, delay: Duration) -> CaptureResult<()> {
+        if self.start.is_none() {
+            self.write_header(image.width(), image.height())?;
+        }
+        let (x, y, width, height) = match self.prev.as_ref() {
+            Some(prev) if prev.dimensions() == image.dimensions() => {
+                // an unchanged frame repeats its first pixels to keep the delay
+                let (x, y, width, height) =
+                    frame_delta::changed_bounds(prev, &image).unwrap_or((0, 0, 1, 1));
+                let (left, top) = (x & !1, y & !1);
+                (left, top, width + x - left, height + y - top)
+            }
+            _ => (0, 0, image.width(), image.height()),
+        };
+        let still = match (width, height) == image.dimensions() {
+            true => self.library.encode(&image, self.quality)?,
+            false => {
+                let part = image::imageops::crop_imm(&image, x, y, width, height).to_image();
+                self.library.encode(&part, self.quality)?
+            }
+        };
+
+        let mut frame = Vec::with_capacity(16 + still.len());
+        for value in [x / 2, y / 2, width - 1, height - 1] {
+            frame.extend_from_slice(&u24(value));
+        }
+        frame.extend_from_slice(&u24(delay.as_millis().min(MAX_U24 as u128) as u32));
+        frame.push(NO_BLEND_FLAG);
+        frame.extend_from_slice(image_chunks(&still)?);
+        self.write_chunk(b"ANMF", &frame)?;
+
+        if self.optimize {
+            if let Some(prev) = self.prev.replace(image) {
+                self.pool.recycle(prev.into_raw());
+            }
+        } else {
+            self.pool.recycle(image.into_raw());
+        }
+        Ok(())
+    }
+
+    fn finish(mut self) -> CaptureResult<()> {
+        if let Some(prev) = self.prev.take() {
+            self.pool.recycle(prev.into_raw());
+        }
+        let start = match self.start {
+            Some(start) => start,
+            None => return Ok(()),
+        };
+        let end = self.out.stream_position()?;
+        self.out.seek(SeekFrom::Start(start + 4))?;
+        self.out
+            .write_all(&((end - start - 8) as u32).to_le_bytes())?;
+        self.out.seek(SeekFrom::End(0))?;
+        self.out.flush()?;
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod webp_test {
+    use super::*;
+    use image::codecs::webp::WebPDecoder;
+    use image::{DynamicImage, Rgba};
+    use std::io::Cursor;
+
+    ///
+    /// The chunks of a RIFF container, as their kind and data
+    fn chunks(mut data: &[u8]) -> Vec<([u8; 4], &[u8])> {
+        let mut chunks = Vec::new();
+        while data.len() >= 8 {
+            let kind = [data[0], data[1], data[2], data[3]];
+            let size = u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize;
+            chunks.push((kind, &data[8..8 + size]));
+            data = &data[(8 + size + size % 2).min(data.len())..];
+        }
+        chunks
+    }
+
+    #[test]
+    #[ignore = "needs libwebp and libwebpmux, e.g. `cargo test -- --ignored`"]
+    fn lossless_animation_test() {
+        let config = WebpConfig {
+            lossless: true,
+            ..WebpConfig::default()
+        };
+        let mut data = Cursor::new(Vec::new());
+        let mut writer = WebpWriter::new(&mut data, &config, 0, FramePool::new()).unwrap();
+        let frames: Vec<RgbaImage> = (0..3u32)
+            .map(|i| {
+                RgbaImage::from_fn(40, 30, |x, y| match x / 4 == i + 2 && y > 10 {
+                    true => Rgba([0xff, 0, 0, 0xff]),
+                    false => Rgba([x as u8 * 6, y as u8 * 8, 0x40, 0xff]),
+                })
+            })
+            .collect();
+        for frame in frames.iter() {
+            writer
+                .write_frame(frame.clone(), Duration::from_millis(70))
+                .unwrap();
+        }
+        writer.finish().unwrap();
+        let data = data.into_inner();
+
+        assert_eq!(&data[..4], b"RIFF");
+        assert_eq!(
+            u32::from_le_bytes([data[4], data[5], data[6], data[7]]) as usize,
+            data.len() - 8
+        );
+        let chunks = chunks(&data[12..]);
+        let kinds: Vec<&[u8; 4]> = chunks.iter().map(|(kind, _)| kind).collect();
+        assert_eq!(kinds, [b"VP8X", b"ANIM", b"ANMF", b"ANMF", b"ANMF"]);
+
+        // the first frame is whole, the second only the moved bar from x 8 to 15
+        let frame = chunks[2].1;
+        assert_eq!(&frame[12..15], &u24(70));
+        let second = chunks[3].1;
+        assert_eq!((&second[..3], &second[6..9]), (&u24(4)[..], &u24(7)[..]));
+
+        // a frame holds a still image as it is
+        let mut still = b"RIFF\0\0\0\0WEBP".to_vec();
+        still.extend_from_slice(&frame[16..]);
+        let decoded = WebPDecoder::new(Cursor::new(still)).unwrap();
+        let decoded = DynamicImage::from_decoder(decoded).unwrap().into_rgba8();
+        assert_eq!(decoded, frames[0]);
+    }
+}
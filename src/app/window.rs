@@ -1,4 +1,5 @@
 use glutin::{
+    dpi::{PhysicalPosition, PhysicalSize},
     event_loop::EventLoopProxy,
     window::{Window, WindowId},
     ContextWrapper, PossiblyCurrent, WindowedContext,
@@ -6,10 +7,12 @@ use glutin::{
 use std::collections::HashMap;
 
 use super::{
-    action::Action,
-    canvas::{Bound2, RegionSelector, Renderable},
+    action::{Action, Countdown},
+    canvas::{Bound2, CountdownOverlay, RecordingIndicator, RegionSelector, Renderable},
+    coords::DesktopLayout,
     event::{Event, KeyInputData, MouseData, UserEvent, WindowEventHandler},
     graphics::Graphics,
+    progress::CaptureProgress,
 };
 
 // use log::{debug, info};
@@ -38,8 +41,12 @@ pub struct CanvasWindow {
     pub graphics: Box<dyn Graphics>,
     pub event_proxy: EventLoopProxy<UserEvent>,
     pub region_selector: RegionSelector,
+    pub countdown: CountdownOverlay,
+    pub recording: RecordingIndicator,
     pub invoke_type: Action,
     window_id: Target,
+    /// The monitors the window was placed over
+    layout: DesktopLayout,
 }
 
 impl CanvasWindow {
@@ -48,14 +55,18 @@ impl CanvasWindow {
         graphics: Box<dyn Graphics>,
         event_proxy: EventLoopProxy<UserEvent>,
         window_id: Target,
+        layout: DesktopLayout,
     ) -> Self {
         CanvasWindow {
             windowed_context: Some(windowed_context),
             graphics,
             event_proxy,
             window_id,
+            layout,
             invoke_type: Action::ImageCapture,
             region_selector: RegionSelector::new(),
+            countdown: CountdownOverlay::new(),
+            recording: RecordingIndicator::new(),
         }
     }
 }
@@ -90,9 +101,31 @@ impl CanvasWindow {
             .request_redraw();
     }
 
-    #[allow(unused)]
+    ///
+    /// Maps a position in the window to physical pixels of the virtual desktop.
+    /// The window manager may have moved the window, so it is asked first where
+    /// it can tell.
+    pub fn to_desktop(&self, pos: (i32, i32)) -> (i32, i32) {
+        let window = self.windowed_context.as_ref().unwrap().window();
+        let min = self.layout.virtual_bound().min;
+        let origin = window
+            .inner_position()
+            .unwrap_or_else(|_| PhysicalPosition::new(min.0, min.1));
+        let pos = self.layout.window_to_desktop(
+            origin,
+            PhysicalPosition::new(pos.0 as f64, pos.1 as f64),
+            window.scale_factor(),
+            crate::platform::is_logical_window(window),
+        );
+        (pos.x, pos.y)
+    }
+
+    ///
+    /// The selected region in virtual desktop coordinates. The selector itself
+    /// works in physical pixels of the window, the same space it is drawn in.
     pub fn get_selector_region(&self) -> Bound2 {
-        self.region_selector.bound
+        let bound = self.region_selector.bound;
+        Bound2::new(self.to_desktop(bound.min), self.to_desktop(bound.max))
     }
 }
 
@@ -104,18 +137,27 @@ impl WindowEventHandler for CanvasWindow {
 
     #[allow(unused)]
     fn on_mouse_release_event(&mut self, data: &MouseData) {
-        let bound = self.region_selector.bound;
-        if bound.empty() == false {
-            let action = match self.invoke_type {
-                Action::ImageCapture => Action::DoImageCapture(bound),
-                Action::GifCapture => Action::DoGifCapture(bound),
-                _ => {
-                    panic!("unexpected action");
-                }
-            };
-            self.send_user_event(Target::Action, Event::DoAction(action));
-            self.request_redraw();
-        }
+        let bound = self.get_selector_region();
+        // dragging selects a region, a single click selects the window under it
+        let countdown = Countdown::Default;
+        let action = match (self.invoke_type, bound.empty()) {
+            (Action::ImageCapture, false) => Action::DoImageCapture(bound, countdown),
+            (Action::GifCapture(format, settings), false) => {
+                Action::DoGifCapture(bound, countdown, format, settings)
+            }
+            (Action::ImageCapture, true) => Action::DoWindowImageCapture(bound.min, countdown),
+            (Action::GifCapture(format, settings), true) => {
+                Action::DoWindowGifCapture(bound.min, countdown, format, settings)
+            }
+            (Action::Timelapse, false) => Action::DoTimelapse(bound, countdown),
+            // a timelapse needs a region, a click just closes the selector
+            (Action::Timelapse, true) => Action::Suspend,
+            _ => {
+                panic!("unexpected action");
+            }
+        };
+        self.send_user_event(Target::Action, Event::DoAction(action));
+        self.request_redraw();
     }
 
     fn send_user_event(&self, receiver: Target, event: Event) {
@@ -141,9 +183,22 @@ impl WindowEventHandler for CanvasWindow {
         }
     }
 
+    fn on_resize_event(&mut self, size: PhysicalSize<u32>) {
+        self.graphics.resize(size.width, size.height);
+        self.request_redraw();
+    }
+
     fn handle_redraw_event(&mut self) {
-        self.graphics.clear((0.0, 0.0, 0.0, 0.5));
-        self.region_selector.update(&*self.graphics); // ???
+        if self.countdown.is_running() {
+            // the desktop is left clear to set it up for the capture
+            self.graphics.clear((0.0, 0.0, 0.0, 0.0));
+            self.region_selector.update(&*self.graphics);
+            self.countdown.update(&*self.graphics);
+        } else {
+            self.graphics.clear((0.0, 0.0, 0.0, 0.5));
+            self.region_selector.update(&*self.graphics); // ???
+            self.recording.update(&*self.graphics);
+        }
         self.swap_buffers();
     }
 
@@ -155,38 +210,54 @@ impl WindowEventHandler for CanvasWindow {
                 self.set_visible(true);
                 self.invoke_type = action;
             }
+            crate::app::event::Event::Recording(state) => {
+                self.recording.state = Some(state);
+                self.recording.bound = self.region_selector.bound;
+                self.request_redraw();
+            }
+            // a recording that ended by itself or failed takes its indicator along
+            crate::app::event::Event::Progress(
+                CaptureProgress::Finished(_) | CaptureProgress::Failed(..),
+            ) if self.recording.state.is_some() => {
+                self.recording.state = None;
+                self.request_redraw();
+            }
+            crate::app::event::Event::Countdown(0) => {
+                self.set_visible(false);
+            }
+            crate::app::event::Event::Countdown(remaining) => {
+                if !self.countdown.is_running() {
+                    let bound = self.region_selector.bound;
+                    self.countdown.center = (
+                        (bound.min.0 + bound.max.0) / 2,
+                        (bound.min.1 + bound.max.1) / 2,
+                    );
+                    if let Some(context) = self.windowed_context.as_ref() {
+                        crate::platform::set_click_through(context.window(), true);
+                    }
+                }
+                self.countdown.remaining = Some(remaining);
+                self.request_redraw();
+            }
             _ => {}
         }
     }
 
     fn set_visible(&mut self, visible: bool) {
+        // a recording ends with the selector, a new one starts with it
+        self.recording.state = None;
         self.windowed_context.as_ref().map(|f| {
             //info!("set main window visible: {}", visible);
             f.window().set_visible(visible);
 
-            use raw_window_handle::{HasRawWindowHandle, RawWindowHandle, Win32Handle};
-
-            use windows::{
-                core::*, Data::Xml::Dom::*, Win32::Foundation::*, Win32::System::Threading::*,
-                Win32::UI::WindowsAndMessaging::*,
-            };
-            if cfg!(windows) {
-                let handle = f.window().raw_window_handle();
-                unsafe {
-                    match handle {
-                        RawWindowHandle::Win32(Win32Handle {
-                            hwnd, hinstance: _, ..
-                        }) => {
-                            let hwnd = HWND(hwnd as isize);
-                            let mut exstyle =
-                                WINDOW_EX_STYLE(GetWindowLongPtrW(hwnd, GWL_EXSTYLE) as u32);
-                            exstyle = exstyle | WS_EX_TOOLWINDOW;
-                            SetWindowLongW(hwnd, GWL_EXSTYLE, exstyle.0 as i32);
-                        }
-                        _ => (),
-                    }
-                }
-            }
+            crate::platform::config_window(f.window());
         });
+        // input is taken again once out of the way, the pointer stays where it is
+        if self.countdown.is_running() {
+            self.countdown.remaining = None;
+            if let Some(context) = self.windowed_context.as_ref() {
+                crate::platform::set_click_through(context.window(), false);
+            }
+        }
     }
 }
@@ -0,0 +1,35 @@
+use crate::app::capture_backend::CaptureBackend;
+use crate::app::error::{CaptureError, CaptureResult};
+
+pub mod wayland_capture;
+pub mod window;
+pub mod x11_capture;
+pub mod xcomposite;
+
+///
+/// Creates the X11 backend, failing when no X server is reachable
+pub fn create_x11_backend() -> CaptureResult<Box<dyn CaptureBackend>> {
+    match x11_capture::X11CaptureBackend::new() {
+        Some(backend) => Ok(Box::new(backend)),
+        None => Err(CaptureError::Backend(
+            "failed to connect to the X server".to_string(),
+        )),
+    }
+}
+
+///
+/// Creates the wlr-screencopy backend. Compositors without screencopy still run
+/// Xwayland, so the X11 backend is used as a fallback.
+pub fn create_wayland_backend() -> CaptureResult<Box<dyn CaptureBackend>> {
+    match wayland_capture::WaylandCaptureBackend::new() {
+        Some(backend) => Ok(Box::new(backend)),
+        None => {
+            log::warn!("wlr-screencopy is not available, falling back to X11");
+            create_x11_backend().map_err(|_| {
+                CaptureError::Backend(
+                    "the compositor has neither wlr-screencopy nor Xwayland".to_string(),
+                )
+            })
+        }
+    }
+}
@@ -0,0 +1,607 @@
+use crate::app::canvas::Bound2;
+use crate::app::capture_backend::{blit_frame, CaptureBackend, PixelFormat};
+use crate::app::error::{CaptureError, CaptureResult};
+use crate::app::frame_buffer;
+
+use wayland_client::protocol::{
+    wl_buffer::WlBuffer,
+    wl_output::{self, WlOutput},
+    wl_shm, wl_shm_pool,
+};
+use wayland_client::{Display, EventQueue, GlobalEvent, GlobalManager, Main};
+use wayland_protocols::unstable::xdg_output::v1::client::{
+    zxdg_output_manager_v1::ZxdgOutputManagerV1, zxdg_output_v1,
+};
+use wayland_protocols::wlr::unstable::screencopy::v1::client::{
+    zwlr_screencopy_frame_v1::{self, ZwlrScreencopyFrameV1},
+    zwlr_screencopy_manager_v1::ZwlrScreencopyManagerV1,
+};
+
+use std::cell::{Cell, RefCell};
+use std::os::unix::io::RawFd;
+use std::ptr;
+use std::rc::Rc;
+
+/// Buffer parameters announced by the compositor for a frame
+#[derive(Debug, Clone, Copy, PartialEq)]
+struct BufferInfo {
+    format: wl_shm::Format,
+    width: u32,
+    height: u32,
+    stride: u32,
+}
+
+#[derive(Default)]
+struct FrameState {
+    buffer: Option<BufferInfo>,
+    y_invert: bool,
+    /// `Some(true)` once the copy is ready, `Some(false)` if it failed
+    done: Option<bool>,
+}
+
+/// A `wl_buffer` backed by a memfd mapping
+struct ShmBuffer {
+    info: BufferInfo,
+    fd: RawFd,
+    data: *mut u8,
+    size: usize,
+    pool: Main<wl_shm_pool::WlShmPool>,
+    buffer: Main<WlBuffer>,
+}
+
+impl ShmBuffer {
+    fn new(shm: &Main<wl_shm::WlShm>, info: BufferInfo) -> Option<Self> {
+        let size = (info.stride * info.height) as usize;
+        unsafe {
+            let fd = libc::memfd_create(
+                b"polaroid-screencopy\0".as_ptr() as *const _,
+                libc::MFD_CLOEXEC,
+            );
+            if fd < 0 {
+                return None;
+            }
+            if libc::ftruncate(fd, size as libc::off_t) < 0 {
+                libc::close(fd);
+                return None;
+            }
+            let data = libc::mmap(
+                ptr::null_mut(),
+                size,
+                libc::PROT_READ | libc::PROT_WRITE,
+                libc::MAP_SHARED,
+                fd,
+                0,
+            );
+            if data == libc::MAP_FAILED {
+                libc::close(fd);
+                return None;
+            }
+            let pool = shm.create_pool(fd, size as i32);
+            let buffer = pool.create_buffer(
+                0,
+                info.width as i32,
+                info.height as i32,
+                info.stride as i32,
+                info.format,
+            );
+            Some(ShmBuffer {
+                info,
+                fd,
+                data: data as *mut u8,
+                size,
+                pool,
+                buffer,
+            })
+        }
+    }
+
+    fn bytes(&self) -> &[u8] {
+        unsafe { std::slice::from_raw_parts(self.data, self.size) }
+    }
+}
+
+impl Drop for ShmBuffer {
+    fn drop(&mut self) {
+        self.buffer.destroy();
+        self.pool.destroy();
+        unsafe {
+            libc::munmap(self.data as *mut _, self.size);
+            libc::close(self.fd);
+        }
+    }
+}
+
+/// What the compositor announced about an output
+#[derive(Debug, Clone, Copy)]
+struct OutputInfo {
+    position: (i32, i32),
+    /// Size of the current mode, in physical pixels
+    mode: (i32, i32),
+    /// Turned by a quarter, which swaps the sides of the mode
+    rotated: bool,
+    scale: i32,
+    /// Placement in the compositor's logical space, from xdg-output
+    logical_position: Option<(i32, i32)>,
+    logical_size: Option<(i32, i32)>,
+}
+
+impl OutputInfo {
+    ///
+    /// Where the output is on the desktop, in physical pixels as `coords` lays
+    /// them out: at its logical position and as large as its mode
+    fn bound(&self) -> Bound2 {
+        let min = self.logical_position.unwrap_or(self.position);
+        let (width, height) = self.physical_size();
+        Bound2::new(min, (min.0 + width, min.1 + height))
+    }
+
+    fn physical_size(&self) -> (i32, i32) {
+        if self.rotated {
+            (self.mode.1, self.mode.0)
+        } else {
+            self.mode
+        }
+    }
+
+    ///
+    /// Without xdg-output the mode is scaled down, which misses fractional scales
+    fn logical_size(&self) -> (i32, i32) {
+        let (width, height) = self.physical_size();
+        self.logical_size
+            .unwrap_or((width / self.scale, height / self.scale))
+    }
+
+    ///
+    /// The screencopy region, in logical pixels of the output, covering `part`
+    /// of the desktop, and where the physical pixels of its copy start on the
+    /// desktop. The region is rounded outwards so the copy covers all of `part`.
+    fn copy_region(&self, part: Bound2) -> (Bound2, (i32, i32)) {
+        let bound = self.bound();
+        let (width, height) = self.physical_size();
+        let (logical_width, logical_height) = self.logical_size();
+        let scale_x = width as f64 / logical_width.max(1) as f64;
+        let scale_y = height as f64 / logical_height.max(1) as f64;
+        let local = part.translate((-bound.min.0, -bound.min.1));
+        let region = Bound2::new(
+            (
+                (local.min.0 as f64 / scale_x).floor() as i32,
+                (local.min.1 as f64 / scale_y).floor() as i32,
+            ),
+            (
+                (local.max.0 as f64 / scale_x).ceil() as i32,
+                (local.max.1 as f64 / scale_y).ceil() as i32,
+            ),
+        );
+        let origin = (
+            bound.min.0 + (region.min.0 as f64 * scale_x).round() as i32,
+            bound.min.1 + (region.min.1 as f64 * scale_y).round() as i32,
+        );
+        (region, origin)
+    }
+}
+
+/// An output and the buffer its copies are made into
+struct Output {
+    output: Main<WlOutput>,
+    info: Rc<Cell<OutputInfo>>,
+    buffer: Option<ShmBuffer>,
+}
+
+impl Output {
+    fn new(output: Main<WlOutput>) -> Self {
+        let info = Rc::new(Cell::new(OutputInfo {
+            position: (0, 0),
+            mode: (0, 0),
+            rotated: false,
+            scale: 1,
+            logical_position: None,
+            logical_size: None,
+        }));
+        let output_info = info.clone();
+        output.quick_assign(move |_, event, _| {
+            let mut i = output_info.get();
+            match event {
+                wl_output::Event::Geometry {
+                    x, y, transform, ..
+                } => {
+                    i.position = (x, y);
+                    i.rotated = matches!(
+                        transform,
+                        wl_output::Transform::_90
+                            | wl_output::Transform::_270
+                            | wl_output::Transform::Flipped90
+                            | wl_output::Transform::Flipped270
+                    );
+                }
+                wl_output::Event::Mode {
+                    flags,
+                    width,
+                    height,
+                    ..
+                } if flags.contains(wl_output::Mode::Current) => i.mode = (width, height),
+                wl_output::Event::Scale { factor } => i.scale = factor.max(1),
+                _ => (),
+            }
+            output_info.set(i);
+        });
+        Output {
+            output,
+            info,
+            buffer: None,
+        }
+    }
+
+    ///
+    /// Asks xdg-output where the output is in the logical space
+    fn describe(&self, manager: &Main<ZxdgOutputManagerV1>) {
+        let info = self.info.clone();
+        manager
+            .get_xdg_output(&self.output)
+            .quick_assign(move |_, event, _| {
+                let mut i = info.get();
+                match event {
+                    zxdg_output_v1::Event::LogicalPosition { x, y } => {
+                        i.logical_position = Some((x, y))
+                    }
+                    zxdg_output_v1::Event::LogicalSize { width, height } => {
+                        i.logical_size = Some((width, height))
+                    }
+                    _ => (),
+                }
+                info.set(i);
+            });
+    }
+}
+
+///
+/// Captures a region of the desktop through `wlr-screencopy-unstable-v1`, which is
+/// implemented by wlroots based compositors such as sway. Screencopy works on a
+/// single output, so regions spanning several outputs are stitched together
+/// from one copy per output. Regions are in physical desktop pixels like
+/// everywhere else, so scaled outputs are copied at their full resolution.
+pub struct WaylandCaptureBackend {
+    event_queue: EventQueue,
+    shm: Main<wl_shm::WlShm>,
+    manager: Main<ZwlrScreencopyManagerV1>,
+    outputs: Vec<Output>,
+    bound: Bound2,
+    /// Copy of the part of the region on a single output
+    part: Vec<u8>,
+    overlay_cursor: bool,
+    // keeps the connection alive for the proxies above
+    _display: Display,
+}
+
+// All the proxies and the event queue are owned by the backend and only used
+// by whichever thread currently holds it, never concurrently.
+unsafe impl Send for WaylandCaptureBackend {}
+
+impl WaylandCaptureBackend {
+    ///
+    /// Connects to the compositor named by `$WAYLAND_DISPLAY`. Returns `None` when
+    /// there is no compositor or it does not implement screencopy. Outputs
+    /// connected afterwards are not captured.
+    pub fn new() -> Option<Self> {
+        let display = Display::connect_to_env().ok()?;
+        let mut event_queue = display.create_event_queue();
+        let attached = (*display).clone().attach(event_queue.token());
+        let outputs = Rc::new(RefCell::new(Vec::new()));
+        let new_outputs = outputs.clone();
+        let globals = GlobalManager::new_with_cb(&attached, move |event, registry, _| {
+            if let GlobalEvent::New {
+                id,
+                interface,
+                version,
+            } = event
+            {
+                if interface == "wl_output" {
+                    // the scale event comes with version 2
+                    let output = registry.bind::<WlOutput>(version.min(2), id);
+                    new_outputs.borrow_mut().push(Output::new(output));
+                }
+            }
+        });
+        event_queue.sync_roundtrip(&mut (), |_, _, _| {}).ok()?;
+
+        let shm = globals.instantiate_exact::<wl_shm::WlShm>(1).ok()?;
+        let manager = globals
+            .instantiate_exact::<ZwlrScreencopyManagerV1>(1)
+            .ok()?;
+        if let Ok(xdg_manager) = globals.instantiate_range::<ZxdgOutputManagerV1>(1, 3) {
+            for output in outputs.borrow().iter() {
+                output.describe(&xdg_manager);
+            }
+        }
+        // the outputs announce their geometry and mode once bound
+        event_queue.sync_roundtrip(&mut (), |_, _, _| {}).ok()?;
+        let outputs = outputs.replace(Vec::new());
+        if outputs.is_empty() {
+            return None;
+        }
+
+        Some(WaylandCaptureBackend {
+            event_queue,
+            shm,
+            manager,
+            outputs,
+            bound: Bound2::default(),
+            part: Vec::new(),
+            overlay_cursor: false,
+            _display: display,
+        })
+    }
+
+    fn dispatch_until<F: Fn(&FrameState) -> bool>(
+        &mut self,
+        state: &Rc<RefCell<FrameState>>,
+        cond: F,
+    ) -> CaptureResult<()> {
+        while !cond(&state.borrow()) {
+            if let Err(e) = self.event_queue.dispatch(&mut (), |_, _, _| {}) {
+                return Err(CaptureError::Backend(format!(
+                    "wayland dispatch failed: {}",
+                    e
+                )));
+            }
+        }
+        Ok(())
+    }
+
+    fn copy_frame(shm: &ShmBuffer, state: &FrameState, buf: &mut Vec<u8>) {
+        let info = shm.info;
+        let row = info.width as usize * 4;
+        let opaque = matches!(
+            info.format,
+            wl_shm::Format::Xrgb8888 | wl_shm::Format::Xbgr8888
+        );
+        // outputs may differ in their order, frames are all BGRA
+        let rgba = matches!(
+            info.format,
+            wl_shm::Format::Xbgr8888 | wl_shm::Format::Abgr8888
+        );
+        buf.resize(row * info.height as usize, 0);
+        let rows = shm.bytes().chunks_exact(info.stride as usize);
+        let dst_rows = buf.chunks_exact_mut(row);
+        let mut copy = |dst: &mut [u8], src: &[u8]| {
+            dst.copy_from_slice(&src[..row]);
+            if opaque {
+                frame_buffer::set_opaque(dst);
+            }
+            if rgba {
+                frame_buffer::swizzle_rb(dst);
+            }
+        };
+        if state.y_invert {
+            dst_rows.zip(rows.rev()).for_each(|(d, s)| copy(d, s));
+        } else {
+            dst_rows.zip(rows).for_each(|(d, s)| copy(d, s));
+        }
+    }
+
+    ///
+    /// Copies `region`, in logical pixels relative to the output, into
+    /// `self.part`. Returns the size of the copy in physical pixels.
+    fn capture_output(&mut self, index: usize, region: Bound2) -> CaptureResult<(u32, u32)> {
+        let (x, y, width, height) = region.rect();
+        let state = Rc::new(RefCell::new(FrameState::default()));
+        let frame: Main<ZwlrScreencopyFrameV1> = self.manager.capture_output_region(
+            self.overlay_cursor as i32,
+            &self.outputs[index].output,
+            x,
+            y,
+            width as i32,
+            height as i32,
+        );
+        let frame_state = state.clone();
+        frame.quick_assign(move |_, event, _| {
+            let mut s = frame_state.borrow_mut();
+            match event {
+                zwlr_screencopy_frame_v1::Event::Buffer {
+                    format,
+                    width,
+                    height,
+                    stride,
+                } => {
+                    s.buffer = Some(BufferInfo {
+                        format,
+                        width,
+                        height,
+                        stride,
+                    })
+                }
+                zwlr_screencopy_frame_v1::Event::Flags { flags } => {
+                    s.y_invert = flags.contains(zwlr_screencopy_frame_v1::Flags::YInvert)
+                }
+                zwlr_screencopy_frame_v1::Event::Ready { .. } => s.done = Some(true),
+                zwlr_screencopy_frame_v1::Event::Failed => s.done = Some(false),
+                _ => (),
+            }
+        });
+
+        let announced = self
+            .dispatch_until(&state, |s| s.buffer.is_some() || s.done.is_some())
+            .and_then(|_| {
+                state.borrow().buffer.ok_or_else(|| {
+                    CaptureError::Backend(
+                        "screencopy failed before announcing a buffer".to_string(),
+                    )
+                })
+            });
+        let info = match announced {
+            Ok(info) => info,
+            Err(e) => {
+                frame.destroy();
+                return Err(e);
+            }
+        };
+
+        // the buffer is reused as long as the compositor asks for the same layout
+        let output = &mut self.outputs[index];
+        if output.buffer.as_ref().map(|b| b.info) != Some(info) {
+            output.buffer = None;
+            output.buffer = ShmBuffer::new(&self.shm, info);
+        }
+        let wl_buffer = match output.buffer.as_ref() {
+            Some(shm) => shm.buffer.detach(),
+            None => {
+                frame.destroy();
+                return Err(CaptureError::Backend(
+                    "failed to allocate a shm buffer".to_string(),
+                ));
+            }
+        };
+        frame.copy(&wl_buffer);
+
+        let copied = self.dispatch_until(&state, |s| s.done.is_some());
+        frame.destroy();
+        copied?;
+        if state.borrow().done == Some(true) {
+            let shm = self.outputs[index].buffer.as_ref().unwrap();
+            Self::copy_frame(shm, &state.borrow(), &mut self.part);
+            Ok((info.width, info.height))
+        } else {
+            Err(CaptureError::Backend(format!(
+                "screencopy failed for {:?} of output {}",
+                region, index
+            )))
+        }
+    }
+}
+
+impl CaptureBackend for WaylandCaptureBackend {
+    fn name(&self) -> &'static str {
+        "wlr-screencopy"
+    }
+
+    fn open(&mut self, region: Bound2) -> CaptureResult<()> {
+        let on_output = self
+            .outputs
+            .iter()
+            .any(|output| region.intersection(&output.info.get().bound()).is_some());
+        if !on_output {
+            return Err(CaptureError::Backend(format!(
+                "region {:?} is outside of every output",
+                region
+            )));
+        }
+        self.bound = region;
+        Ok(())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        (self.bound.get_width(), self.bound.get_height())
+    }
+
+    fn frame_bound(&mut self) -> Bound2 {
+        self.bound
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Bgra8
+    }
+
+    /// The compositor knows where the pointer is, clients do not
+    fn set_draw_cursor(&mut self, draw: bool) -> bool {
+        self.overlay_cursor = draw;
+        true
+    }
+
+    fn grab_frame(&mut self, buf: &mut Vec<u8>) -> CaptureResult<()> {
+        let (width, height) = self.size();
+        buf.clear();
+        buf.resize((width * height) as usize * 4, 0);
+        for index in 0..self.outputs.len() {
+            let info = self.outputs[index].info.get();
+            let part = match self.bound.intersection(&info.bound()) {
+                Some(part) => part,
+                None => continue,
+            };
+            let (region, origin) = info.copy_region(part);
+            let (w, h) = self.capture_output(index, region)?;
+            let copied = Bound2::new(origin, (origin.0 + w as i32, origin.1 + h as i32));
+            blit_frame(buf, self.bound, &self.part, copied);
+        }
+        Ok(())
+    }
+}
+
+#[cfg(test)]
+mod wayland_capture_test {
+    use super::*;
+
+    fn output(position: (i32, i32), mode: (i32, i32), scale: i32) -> OutputInfo {
+        OutputInfo {
+            position,
+            mode,
+            rotated: false,
+            scale,
+            logical_position: Some(position),
+            logical_size: Some((mode.0 / scale, mode.1 / scale)),
+        }
+    }
+
+    /// A region selected on the 2x output of a mixed DPI desktop is copied from
+    /// the right logical region of it, at full resolution
+    #[test]
+    fn selector_region_on_scaled_output_test() {
+        use crate::app::coords::{DesktopLayout, MonitorInfo, PhysicalPosition};
+
+        let outputs = [
+            output((0, 0), (1920, 1080), 1),
+            output((1920, 0), (2560, 1440), 2),
+        ];
+        // glutin reports the monitors at their logical position with their
+        // physical size
+        let layout = DesktopLayout::new(vec![
+            MonitorInfo {
+                bound: Bound2::new((0, 0), (1920, 1080)),
+                scale_factor: 1.0,
+            },
+            MonitorInfo {
+                bound: Bound2::new((1920, 0), (1920 + 2560, 1440)),
+                scale_factor: 2.0,
+            },
+        ]);
+        for (output, monitor) in outputs.iter().zip(&layout.monitors) {
+            assert_eq!(output.bound(), monitor.bound);
+        }
+
+        // the selector is a logical window with scale 2 over the whole desktop
+        let to_desktop = |x: f64, y: f64| {
+            let origin = PhysicalPosition::new(0, 0);
+            let pos = layout.window_to_desktop(origin, PhysicalPosition::new(x, y), 2.0, true);
+            (pos.x, pos.y)
+        };
+        let region = Bound2::new(
+            to_desktop(2.0 * 2020.0, 2.0 * 10.0),
+            to_desktop(2.0 * 2120.0, 2.0 * 60.0),
+        );
+        assert_eq!(region, Bound2::new((2120, 20), (2320, 120)));
+
+        assert!(region.intersection(&outputs[0].bound()).is_none());
+        let part = region.intersection(&outputs[1].bound()).unwrap();
+        let (logical, origin) = outputs[1].copy_region(part);
+        assert_eq!(logical, Bound2::new((100, 10), (200, 60)));
+        // the compositor copies those 100x50 logical pixels into 200x100 ones
+        let copied = Bound2::new(origin, (origin.0 + 200, origin.1 + 100));
+        assert_eq!(copied, region);
+    }
+
+    #[test]
+    fn rotated_output_bound_test() {
+        let mut info = output((0, 0), (1920, 1080), 1);
+        info.rotated = true;
+        info.logical_size = None;
+        assert_eq!(info.bound(), Bound2::new((0, 0), (1080, 1920)));
+        assert_eq!(info.logical_size(), (1080, 1920));
+    }
+
+    #[test]
+    #[ignore = "needs a screencopy compositor, e.g. `WLR_BACKENDS=headless sway`, then `cargo test -- --ignored`"]
+    fn capture_output_region_test() {
+        let mut backend = WaylandCaptureBackend::new().unwrap();
+        backend.open(Bound2::new((0, 0), (64, 32))).unwrap();
+        let img = backend.capture_image().unwrap();
+        assert_eq!(img.dimensions(), (64, 32));
+    }
+}
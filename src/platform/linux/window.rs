@@ -0,0 +1,60 @@
+use glutin::platform::unix::{WindowBuilderExtUnix, WindowExtUnix, XWindowType};
+use glutin::window::{Window, WindowBuilder};
+use std::os::raw::c_int;
+use x11_dl::{xfixes, xlib};
+
+/// `ShapeInput` of the X Shape extension
+const SHAPE_INPUT: c_int = 2;
+
+///
+/// Asks the X11 window manager to treat the selector as a utility window, which
+/// keeps it out of the taskbar and the window switcher
+pub fn config_window_builder(wb: WindowBuilder) -> WindowBuilder {
+    wb.with_x11_window_type(vec![XWindowType::Utility])
+}
+
+///
+/// The window type hint is applied at creation time on X11, nothing is left to
+/// do once the window exists
+#[allow(unused)]
+pub fn config_window(window: &Window) {}
+
+///
+/// Wayland surfaces are placed in the logical space of the compositor, X11
+/// windows in physical pixels
+pub fn is_logical_window(window: &Window) -> bool {
+    window.wayland_surface().is_some()
+}
+
+///
+/// Lets the mouse reach the windows below, so the desktop can be used while an
+/// overlay is shown. X11 windows get an empty input shape, Wayland windows keep
+/// taking input.
+pub fn set_click_through(window: &Window, click_through: bool) {
+    let (display, xwindow) = match (window.xlib_display(), window.xlib_window()) {
+        (Some(display), Some(xwindow)) => (display as *mut xlib::Display, xwindow),
+        _ => return,
+    };
+    let (xlib, xfixes) = match (xlib::Xlib::open(), xfixes::Xlib::open()) {
+        (Ok(xlib), Ok(xfixes)) => (xlib, xfixes),
+        _ => {
+            log::warn!("XFixes is not available, the overlay keeps taking input");
+            return;
+        }
+    };
+    unsafe {
+        let (mut event_base, mut error_base) = (0, 0);
+        if (xfixes.XFixesQueryExtension)(display, &mut event_base, &mut error_base) == 0 {
+            return;
+        }
+        if click_through {
+            let region = (xfixes.XFixesCreateRegion)(display, std::ptr::null_mut(), 0);
+            (xfixes.XFixesSetWindowShapeRegion)(display, xwindow, SHAPE_INPUT, 0, 0, region);
+            (xfixes.XFixesDestroyRegion)(display, region);
+        } else {
+            // no region restores the default input shape
+            (xfixes.XFixesSetWindowShapeRegion)(display, xwindow, SHAPE_INPUT, 0, 0, 0);
+        }
+        (xlib.XFlush)(display);
+    }
+}
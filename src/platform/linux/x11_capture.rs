@@ -0,0 +1,762 @@
+use crate::app::canvas::Bound2;
+use crate::app::capture_backend::{CaptureBackend, CursorImage, PixelFormat, WindowSelector};
+use crate::app::error::{CaptureError, CaptureResult};
+use crate::app::frame_buffer;
+
+use super::xcomposite::{XComposite, COMPOSITE_REDIRECT_AUTOMATIC};
+
+use image::RgbaImage;
+use x11_dl::xfixes;
+use x11_dl::xlib::{self, Display, XErrorEvent, XImage, Xlib};
+use x11_dl::xshm::Xext;
+
+use std::ffi::CStr;
+use std::os::raw::{c_char, c_int, c_uchar, c_uint, c_ulong};
+use std::ptr;
+use std::sync::atomic::{AtomicBool, Ordering};
+
+/// Same layout as `XShmSegmentInfo`, whose fields `x11-dl` keeps private
+#[repr(C)]
+struct ShmSegmentInfo {
+    shmseg: c_ulong,
+    shmid: c_int,
+    shmaddr: *mut c_char,
+    read_only: xlib::Bool,
+}
+
+/// A shared memory XImage sized for the opened region
+struct ShmImage {
+    image: *mut XImage,
+    info: Box<ShmSegmentInfo>,
+}
+
+static X_ERROR_OCCURRED: AtomicBool = AtomicBool::new(false);
+
+unsafe extern "C" fn record_x_error(_: *mut Display, _: *mut XErrorEvent) -> c_int {
+    X_ERROR_OCCURRED.store(true, Ordering::SeqCst);
+    0
+}
+
+/// A top level window being captured
+struct TargetWindow {
+    window: xlib::Window,
+    /// Frame size, fixed when the window was opened
+    size: (u32, u32),
+    redirected: bool,
+}
+
+///
+/// Captures a region of the X11 root window. Frames are transferred through a
+/// MIT-SHM segment when the server supports it, otherwise with plain `XGetImage`.
+/// The root window spans all monitors, so regions straddling them need no
+/// stitching.
+///
+/// Single windows are read from their XComposite backing pixmap when available,
+/// which keeps the content valid while the window is covered by others.
+pub struct X11CaptureBackend {
+    xlib: Xlib,
+    xext: Option<Xext>,
+    xfixes: Option<xfixes::Xlib>,
+    xcomposite: Option<XComposite>,
+    display: *mut Display,
+    root: xlib::Window,
+    bound: Bound2,
+    shm: Option<ShmImage>,
+    window: Option<TargetWindow>,
+    /// Whether the screen has the 32 bit little endian pixels `copy_image` reads
+    supported_format: bool,
+}
+
+unsafe impl Send for X11CaptureBackend {}
+
+impl X11CaptureBackend {
+    ///
+    /// Connects to the display named by `$DISPLAY`. Returns `None` when Xlib is
+    /// not available or there is no X server to talk to.
+    pub fn new() -> Option<Self> {
+        let xlib = Xlib::open().ok()?;
+        unsafe {
+            let display = (xlib.XOpenDisplay)(ptr::null());
+            if display.is_null() {
+                return None;
+            }
+            let root = (xlib.XDefaultRootWindow)(display);
+            let xext = Xext::open()
+                .ok()
+                .filter(|xext| (xext.XShmQueryExtension)(display) != 0);
+            let xfixes = xfixes::Xlib::open().ok().filter(|xfixes| {
+                let (mut event_base, mut error_base) = (0, 0);
+                (xfixes.XFixesQueryExtension)(display, &mut event_base, &mut error_base) != 0
+            });
+            let xcomposite = XComposite::open().filter(|xcomposite| {
+                let (mut event_base, mut error_base) = (0, 0);
+                (xcomposite.XCompositeQueryExtension)(display, &mut event_base, &mut error_base)
+                    != 0
+            });
+            let supported_format = Self::has_supported_format(&xlib, display);
+            Some(X11CaptureBackend {
+                xlib,
+                xext,
+                xfixes,
+                xcomposite,
+                display,
+                root,
+                bound: Bound2::default(),
+                shm: None,
+                window: None,
+                supported_format,
+            })
+        }
+    }
+
+    ///
+    /// Whether ZPixmap images of the default depth hold 32 bit pixels in the
+    /// little endian byte order
+    unsafe fn has_supported_format(xlib: &Xlib, display: *mut Display) -> bool {
+        let depth = (xlib.XDefaultDepth)(display, (xlib.XDefaultScreen)(display));
+        let mut count = 0;
+        let formats = (xlib.XListPixmapFormats)(display, &mut count);
+        if formats.is_null() {
+            return false;
+        }
+        let bits_per_pixel = std::slice::from_raw_parts(formats, count as usize)
+            .iter()
+            .find(|format| format.depth == depth)
+            .map(|format| format.bits_per_pixel);
+        (xlib.XFree)(formats as *mut _);
+        bits_per_pixel == Some(32) && (xlib.XImageByteOrder)(display) == xlib::LSBFirst
+    }
+
+    fn check_format(&self) -> CaptureResult<()> {
+        if self.supported_format {
+            Ok(())
+        } else {
+            Err(CaptureError::Backend(
+                "the X server's pixel format is not 32 bit little endian".to_string(),
+            ))
+        }
+    }
+
+    ///
+    /// Whether frames are transferred through shared memory
+    #[allow(unused)]
+    pub fn uses_shm(&self) -> bool {
+        self.shm.is_some()
+    }
+
+    fn root_size(&self) -> (u32, u32) {
+        unsafe {
+            let mut attrs: xlib::XWindowAttributes = std::mem::zeroed();
+            (self.xlib.XGetWindowAttributes)(self.display, self.root, &mut attrs);
+            (attrs.width as u32, attrs.height as u32)
+        }
+    }
+
+    ///
+    /// Runs `f` with X errors recorded instead of terminating the process.
+    /// Returns `None` if any request made by `f` failed. Nested in another
+    /// trap, the errors count for the outer one as well.
+    fn trap_errors<T, F: FnOnce() -> T>(&self, f: F) -> Option<T> {
+        unsafe {
+            let outer_failed = X_ERROR_OCCURRED.swap(false, Ordering::SeqCst);
+            let old_handler = (self.xlib.XSetErrorHandler)(Some(record_x_error));
+            let result = f();
+            (self.xlib.XSync)(self.display, xlib::False);
+            (self.xlib.XSetErrorHandler)(old_handler);
+            let failed = X_ERROR_OCCURRED.load(Ordering::SeqCst);
+            X_ERROR_OCCURRED.store(outer_failed || failed, Ordering::SeqCst);
+            if failed {
+                None
+            } else {
+                Some(result)
+            }
+        }
+    }
+
+    fn intern_atom(&self, name: &[u8]) -> xlib::Atom {
+        unsafe { (self.xlib.XInternAtom)(self.display, name.as_ptr() as *const _, xlib::False) }
+    }
+
+    ///
+    /// Reads a window property as raw bytes, along with its format in bits
+    fn get_property(
+        &self,
+        window: xlib::Window,
+        property: xlib::Atom,
+        ty: xlib::Atom,
+    ) -> Option<(Vec<u8>, c_int)> {
+        unsafe {
+            let mut actual_type = 0;
+            let mut format = 0;
+            let mut items = 0;
+            let mut remaining = 0;
+            let mut data: *mut c_uchar = ptr::null_mut();
+            let status = (self.xlib.XGetWindowProperty)(
+                self.display,
+                window,
+                property,
+                0,
+                1024,
+                xlib::False,
+                ty,
+                &mut actual_type,
+                &mut format,
+                &mut items,
+                &mut remaining,
+                &mut data,
+            );
+            if status != xlib::Success as c_int || data.is_null() {
+                return None;
+            }
+            // 32 bit items are stored as longs
+            let item_size = match format {
+                8 => 1,
+                16 => 2,
+                _ => std::mem::size_of::<c_ulong>(),
+            };
+            let bytes = std::slice::from_raw_parts(data, items as usize * item_size).to_vec();
+            (self.xlib.XFree)(data as *mut _);
+            if actual_type == 0 {
+                None
+            } else {
+                Some((bytes, format))
+            }
+        }
+    }
+
+    fn children(&self, window: xlib::Window) -> Vec<xlib::Window> {
+        unsafe {
+            let (mut root, mut parent) = (0, 0);
+            let mut children: *mut xlib::Window = ptr::null_mut();
+            let mut count: c_uint = 0;
+            let status = (self.xlib.XQueryTree)(
+                self.display,
+                window,
+                &mut root,
+                &mut parent,
+                &mut children,
+                &mut count,
+            );
+            if status == 0 || children.is_null() {
+                return Vec::new();
+            }
+            let list = std::slice::from_raw_parts(children, count as usize).to_vec();
+            (self.xlib.XFree)(children as *mut _);
+            list
+        }
+    }
+
+    ///
+    /// Finds the application window inside a top level window, which is a frame
+    /// window of the window manager in most setups
+    fn client_window(&self, window: xlib::Window) -> Option<xlib::Window> {
+        let wm_state = self.intern_atom(b"WM_STATE\0");
+        if self
+            .get_property(window, wm_state, xlib::AnyPropertyType as xlib::Atom)
+            .is_some()
+        {
+            return Some(window);
+        }
+        self.children(window)
+            .into_iter()
+            .find_map(|child| self.client_window(child))
+    }
+
+    fn window_title(&self, window: xlib::Window) -> Option<String> {
+        let net_wm_name = self.intern_atom(b"_NET_WM_NAME\0");
+        let utf8_string = self.intern_atom(b"UTF8_STRING\0");
+        if let Some((bytes, _)) = self.get_property(window, net_wm_name, utf8_string) {
+            return Some(String::from_utf8_lossy(&bytes).into_owned());
+        }
+        unsafe {
+            let mut name: *mut c_char = ptr::null_mut();
+            if (self.xlib.XFetchName)(self.display, window, &mut name) == 0 || name.is_null() {
+                return None;
+            }
+            let title = CStr::from_ptr(name).to_string_lossy().into_owned();
+            (self.xlib.XFree)(name as *mut _);
+            Some(title)
+        }
+    }
+
+    fn window_pid(&self, window: xlib::Window) -> Option<u32> {
+        let net_wm_pid = self.intern_atom(b"_NET_WM_PID\0");
+        let (bytes, format) = self.get_property(window, net_wm_pid, xlib::XA_CARDINAL)?;
+        if format != 32 || bytes.len() < std::mem::size_of::<c_ulong>() {
+            return None;
+        }
+        const SIZE: usize = std::mem::size_of::<c_ulong>();
+        let mut pid = [0u8; SIZE];
+        pid.copy_from_slice(&bytes[..SIZE]);
+        Some(c_ulong::from_ne_bytes(pid) as u32)
+    }
+
+    fn window_attributes(&self, window: xlib::Window) -> Option<xlib::XWindowAttributes> {
+        self.trap_errors(|| unsafe {
+            let mut attrs: xlib::XWindowAttributes = std::mem::zeroed();
+            let status = (self.xlib.XGetWindowAttributes)(self.display, window, &mut attrs);
+            (status != 0).then_some(attrs)
+        })
+        .flatten()
+    }
+
+    ///
+    /// Visible top level windows from the topmost down, leaving out the windows
+    /// of this process such as the region selector
+    fn top_level_windows(&self) -> Vec<(xlib::Window, xlib::XWindowAttributes)> {
+        let pid = std::process::id();
+        self.children(self.root)
+            .into_iter()
+            .rev()
+            .filter_map(|window| {
+                let attrs = self.window_attributes(window)?;
+                let viewable =
+                    attrs.map_state == xlib::IsViewable && attrs.class == xlib::InputOutput;
+                let own = self
+                    .client_window(window)
+                    .and_then(|client| self.window_pid(client))
+                    == Some(pid);
+                (viewable && !own).then_some((window, attrs))
+            })
+            .collect()
+    }
+
+    fn close_window(&mut self) {
+        if let Some(target) = self.window.take() {
+            if let (true, Some(xcomposite)) = (target.redirected, self.xcomposite.as_ref()) {
+                self.trap_errors(|| unsafe {
+                    (xcomposite.XCompositeUnredirectWindow)(
+                        self.display,
+                        target.window,
+                        COMPOSITE_REDIRECT_AUTOMATIC,
+                    )
+                });
+            }
+        }
+    }
+
+    fn grab_window(&self, target: &TargetWindow, buf: &mut Vec<u8>) -> CaptureResult<()> {
+        let (frame_width, frame_height) = target.size;
+        buf.clear();
+        buf.resize((frame_width * frame_height) as usize * 4, 0);
+        let attrs = self
+            .window_attributes(target.window)
+            .ok_or_else(|| CaptureError::Backend(format!("window {:#x} is gone", target.window)))?;
+        let width = (attrs.width as u32).min(frame_width);
+        let height = (attrs.height as u32).min(frame_height);
+
+        // an image may come back even if a request of the trap failed
+        let mut image = ptr::null_mut();
+        let grabbed = self.trap_errors(|| unsafe {
+            let pixmap = match (target.redirected, self.xcomposite.as_ref()) {
+                (true, Some(xcomposite)) => {
+                    (xcomposite.XCompositeNameWindowPixmap)(self.display, target.window)
+                }
+                _ => 0,
+            };
+            let drawable = if pixmap != 0 { pixmap } else { target.window };
+            image = (self.xlib.XGetImage)(
+                self.display,
+                drawable,
+                0,
+                0,
+                width,
+                height,
+                (self.xlib.XAllPlanes)(),
+                xlib::ZPixmap,
+            );
+            if pixmap != 0 {
+                (self.xlib.XFreePixmap)(self.display, pixmap);
+            }
+        });
+        unsafe {
+            let copied = if grabbed.is_some() && !image.is_null() {
+                Self::copy_image(image, buf, frame_width)
+            } else {
+                Err(CaptureError::Backend(format!(
+                    "failed to read window {:#x}",
+                    target.window
+                )))
+            };
+            if !image.is_null() {
+                (self.xlib.XDestroyImage)(image);
+            }
+            copied
+        }
+    }
+
+    fn create_shm_image(&self, width: u32, height: u32) -> Option<ShmImage> {
+        let xext = self.xext.as_ref()?;
+        unsafe {
+            let screen = (self.xlib.XDefaultScreen)(self.display);
+            let visual = (self.xlib.XDefaultVisual)(self.display, screen);
+            let depth = (self.xlib.XDefaultDepth)(self.display, screen);
+            let mut info = Box::new(ShmSegmentInfo {
+                shmseg: 0,
+                shmid: -1,
+                shmaddr: ptr::null_mut(),
+                read_only: xlib::False,
+            });
+            let image = (xext.XShmCreateImage)(
+                self.display,
+                visual,
+                depth as u32,
+                xlib::ZPixmap,
+                ptr::null_mut(),
+                &mut *info as *mut ShmSegmentInfo as *mut _,
+                width,
+                height,
+            );
+            if image.is_null() {
+                return None;
+            }
+
+            let size = (*image).bytes_per_line as usize * (*image).height as usize;
+            info.shmid = libc::shmget(libc::IPC_PRIVATE, size, libc::IPC_CREAT | 0o600);
+            if info.shmid < 0 {
+                (self.xlib.XDestroyImage)(image);
+                return None;
+            }
+            let addr = libc::shmat(info.shmid, ptr::null(), 0);
+            if addr as isize == -1 {
+                libc::shmctl(info.shmid, libc::IPC_RMID, ptr::null_mut());
+                (self.xlib.XDestroyImage)(image);
+                return None;
+            }
+            info.shmaddr = addr as *mut c_char;
+            (*image).data = info.shmaddr;
+
+            // attaching fails on remote displays, which must not abort the process
+            X_ERROR_OCCURRED.store(false, Ordering::SeqCst);
+            let old_handler = (self.xlib.XSetErrorHandler)(Some(record_x_error));
+            let attached =
+                (xext.XShmAttach)(self.display, &mut *info as *mut ShmSegmentInfo as *mut _) != 0;
+            (self.xlib.XSync)(self.display, xlib::False);
+            (self.xlib.XSetErrorHandler)(old_handler);
+
+            // the segment is freed once both sides have detached
+            libc::shmctl(info.shmid, libc::IPC_RMID, ptr::null_mut());
+
+            if !attached || X_ERROR_OCCURRED.load(Ordering::SeqCst) {
+                (*image).data = ptr::null_mut();
+                (self.xlib.XDestroyImage)(image);
+                libc::shmdt(addr);
+                return None;
+            }
+            Some(ShmImage { image, info })
+        }
+    }
+
+    fn destroy_shm_image(&mut self) {
+        if let (Some(mut shm), Some(xext)) = (self.shm.take(), self.xext.as_ref()) {
+            unsafe {
+                (xext.XShmDetach)(
+                    self.display,
+                    &mut *shm.info as *mut ShmSegmentInfo as *mut _,
+                );
+                (self.xlib.XSync)(self.display, xlib::False);
+                (*shm.image).data = ptr::null_mut();
+                (self.xlib.XDestroyImage)(shm.image);
+                libc::shmdt(shm.info.shmaddr as *const _);
+            }
+        }
+    }
+
+    ///
+    /// Copies the rows of `image` to the top left of a `frame_width` wide frame
+    /// in `buf`, dropping the row padding and forcing the unused padding byte to
+    /// be opaque. `buf` must already hold at least as many rows as the image.
+    /// Fails, copying nothing, unless the image has 32 bit little endian pixels.
+    unsafe fn copy_image(
+        image: *const XImage,
+        buf: &mut [u8],
+        frame_width: u32,
+    ) -> CaptureResult<()> {
+        if (*image).bits_per_pixel != 32 || (*image).byte_order != xlib::LSBFirst {
+            return Err(CaptureError::Backend(format!(
+                "cannot read {} bit images in byte order {}",
+                (*image).bits_per_pixel,
+                (*image).byte_order
+            )));
+        }
+        let width = ((*image).width as u32).min(frame_width) as usize;
+        let height = (*image).height as usize;
+        let stride = (*image).bytes_per_line as usize;
+        let row = width * 4;
+        let data = std::slice::from_raw_parts((*image).data as *const u8, stride * height);
+
+        for (dst, src) in buf
+            .chunks_exact_mut(frame_width as usize * 4)
+            .zip(data.chunks_exact(stride))
+        {
+            dst[..row].copy_from_slice(&src[..row]);
+            frame_buffer::set_opaque(&mut dst[..row]);
+        }
+        Ok(())
+    }
+}
+
+impl Drop for X11CaptureBackend {
+    fn drop(&mut self) {
+        self.close_window();
+        self.destroy_shm_image();
+        unsafe {
+            (self.xlib.XCloseDisplay)(self.display);
+        }
+    }
+}
+
+impl CaptureBackend for X11CaptureBackend {
+    fn name(&self) -> &'static str {
+        "x11"
+    }
+
+    fn open(&mut self, region: Bound2) -> CaptureResult<()> {
+        self.close_window();
+        self.destroy_shm_image();
+        self.bound = Bound2::new((0, 0), (0, 0));
+        self.check_format()?;
+        // requesting pixels outside of the root window is a BadMatch error
+        let (root_width, root_height) = self.root_size();
+        let root = Bound2::new((0, 0), (root_width as i32, root_height as i32));
+        self.bound = region.intersection(&root).ok_or_else(|| {
+            CaptureError::Backend(format!("region {:?} is outside of the root window", region))
+        })?;
+        let region = self.bound;
+        self.shm = self.create_shm_image(region.get_width(), region.get_height());
+        if self.shm.is_none() {
+            log::info!("MIT-SHM is unavailable, falling back to XGetImage");
+        }
+        Ok(())
+    }
+
+    fn find_window(&mut self, selector: &WindowSelector) -> Option<u64> {
+        match selector {
+            WindowSelector::Id(id) => Some(*id),
+            WindowSelector::Title(title) => self
+                .top_level_windows()
+                .into_iter()
+                .find(|(window, _)| {
+                    self.client_window(*window)
+                        .and_then(|client| self.window_title(client))
+                        .is_some_and(|name| name.contains(title.as_str()))
+                })
+                .map(|(window, _)| window),
+            WindowSelector::At(x, y) => self
+                .top_level_windows()
+                .into_iter()
+                .find(|(_, attrs)| {
+                    let border = attrs.border_width;
+                    *x >= attrs.x
+                        && *y >= attrs.y
+                        && *x < attrs.x + attrs.width + border * 2
+                        && *y < attrs.y + attrs.height + border * 2
+                })
+                .map(|(window, _)| window),
+        }
+    }
+
+    fn open_window(&mut self, window: u64) -> CaptureResult<()> {
+        self.close_window();
+        self.destroy_shm_image();
+        self.check_format()?;
+        let window = window as xlib::Window;
+        let attrs = self
+            .window_attributes(window)
+            .ok_or_else(|| CaptureError::Backend(format!("window {:#x} is gone", window)))?;
+        let redirected = match self.xcomposite.as_ref() {
+            Some(xcomposite) => self
+                .trap_errors(|| unsafe {
+                    (xcomposite.XCompositeRedirectWindow)(
+                        self.display,
+                        window,
+                        COMPOSITE_REDIRECT_AUTOMATIC,
+                    )
+                })
+                .is_some(),
+            None => false,
+        };
+        if !redirected {
+            log::info!("XComposite is unavailable, covered parts of the window are not captured");
+        }
+        self.window = Some(TargetWindow {
+            window,
+            size: (attrs.width as u32, attrs.height as u32),
+            redirected,
+        });
+        self.bound = self.frame_bound();
+        Ok(())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        match self.window.as_ref() {
+            Some(target) => target.size,
+            None => (self.bound.get_width(), self.bound.get_height()),
+        }
+    }
+
+    fn frame_bound(&mut self) -> Bound2 {
+        let target = match self.window.as_ref() {
+            Some(target) => target,
+            None => return self.bound,
+        };
+        let position = self.trap_errors(|| unsafe {
+            let (mut x, mut y, mut child) = (0, 0, 0);
+            (self.xlib.XTranslateCoordinates)(
+                self.display,
+                target.window,
+                self.root,
+                0,
+                0,
+                &mut x,
+                &mut y,
+                &mut child,
+            );
+            (x, y)
+        });
+        let (x, y) = position.unwrap_or(self.bound.min);
+        Bound2::new((x, y), (x + target.size.0 as i32, y + target.size.1 as i32))
+    }
+
+    fn cursor_image(&mut self) -> Option<CursorImage> {
+        let xfixes = self.xfixes.as_ref()?;
+        unsafe {
+            let cursor = (xfixes.XFixesGetCursorImage)(self.display);
+            if cursor.is_null() {
+                return None;
+            }
+            let c = &*cursor;
+            let pixels = std::slice::from_raw_parts(c.pixels, c.width as usize * c.height as usize);
+            // each long holds one premultiplied ARGB pixel
+            let mut image = RgbaImage::new(c.width as u32, c.height as u32);
+            for (dst, &argb) in image.pixels_mut().zip(pixels) {
+                let argb = argb as u32;
+                let a = argb >> 24;
+                let unpremultiply =
+                    |v: u32| (v * 255).checked_div(a).map_or(0, |v| v.min(255) as u8);
+                dst.0 = [
+                    unpremultiply((argb >> 16) & 0xff),
+                    unpremultiply((argb >> 8) & 0xff),
+                    unpremultiply(argb & 0xff),
+                    a as u8,
+                ];
+            }
+            let cursor_image = CursorImage {
+                image,
+                hotspot: (c.xhot as u32, c.yhot as u32),
+                position: (c.x as i32, c.y as i32),
+            };
+            (self.xlib.XFree)(cursor as *mut _);
+            Some(cursor_image)
+        }
+    }
+
+    fn cursor_position(&mut self) -> Option<(i32, i32)> {
+        unsafe {
+            let (mut root, mut child) = (0, 0);
+            let (mut x, mut y, mut win_x, mut win_y, mut mask) = (0, 0, 0, 0, 0);
+            let on_screen = (self.xlib.XQueryPointer)(
+                self.display,
+                self.root,
+                &mut root,
+                &mut child,
+                &mut x,
+                &mut y,
+                &mut win_x,
+                &mut win_y,
+                &mut mask,
+            );
+            if on_screen != 0 {
+                Some((x, y))
+            } else {
+                None
+            }
+        }
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        // 24 and 32 bit ZPixmaps are laid out as BGRX on little endian servers
+        PixelFormat::Bgra8
+    }
+
+    fn grab_frame(&mut self, buf: &mut Vec<u8>) -> CaptureResult<()> {
+        if let Some(target) = self.window.as_ref() {
+            return self.grab_window(target, buf);
+        }
+        let bound = self.bound;
+        if bound.empty() {
+            return Err(CaptureError::Backend("no region is open".to_string()));
+        }
+        let (x, y, width, height) = bound.rect();
+        buf.resize((width * height) as usize * 4, 0);
+        let failed =
+            move || CaptureError::Backend(format!("failed to read {:?} of the root window", bound));
+        // a failed request must not end in the default handler, which exits
+        unsafe {
+            let all_planes = (self.xlib.XAllPlanes)();
+            if let (Some(shm), Some(xext)) = (self.shm.as_ref(), self.xext.as_ref()) {
+                let grabbed = self.trap_errors(|| {
+                    (xext.XShmGetImage)(self.display, self.root, shm.image, x, y, all_planes as _)
+                });
+                if grabbed.is_some_and(|status| status != 0) {
+                    Self::copy_image(shm.image, buf, width)
+                } else {
+                    Err(failed())
+                }
+            } else {
+                let mut image = ptr::null_mut();
+                let grabbed = self.trap_errors(|| {
+                    image = (self.xlib.XGetImage)(
+                        self.display,
+                        self.root,
+                        x,
+                        y,
+                        width,
+                        height,
+                        all_planes,
+                        xlib::ZPixmap,
+                    );
+                });
+                let copied = if grabbed.is_some() && !image.is_null() {
+                    Self::copy_image(image, buf, width)
+                } else {
+                    Err(failed())
+                };
+                if !image.is_null() {
+                    (self.xlib.XDestroyImage)(image);
+                }
+                copied
+            }
+        }
+    }
+}
+
+#[cfg(test)]
+mod x11_capture_test {
+    use super::*;
+
+    #[test]
+    #[ignore = "needs an X server, e.g. `xvfb-run cargo test -- --ignored`"]
+    fn capture_root_window_region_test() {
+        let mut backend = X11CaptureBackend::new().unwrap();
+        let bound = Bound2::new((0, 0), (64, 32));
+        backend.open(bound).unwrap();
+        let img = backend.capture_image().unwrap();
+        assert_eq!(img.dimensions(), (64, 32));
+        assert!(img.pixels().all(|p| p.0[3] == 0xff));
+    }
+
+    #[test]
+    #[ignore = "needs an X server, e.g. `xvfb-run cargo test -- --ignored`"]
+    fn region_outside_root_window_test() {
+        let mut backend = X11CaptureBackend::new().unwrap();
+        let (root_width, _) = backend.root_size();
+        let right = root_width as i32 + 10;
+        assert!(backend
+            .open(Bound2::new((right, 0), (right + 64, 32)))
+            .is_err());
+        let mut buf = Vec::new();
+        assert!(backend.grab_frame(&mut buf).is_err());
+        assert!(buf.is_empty());
+    }
+}
@@ -0,0 +1,74 @@
+use x11_dl::xlib::{Bool, Display, Pixmap, Window};
+
+use std::ffi::CStr;
+use std::os::raw::{c_int, c_void};
+
+pub const COMPOSITE_REDIRECT_AUTOMATIC: c_int = 0;
+
+type QueryExtensionFn = unsafe extern "C" fn(*mut Display, *mut c_int, *mut c_int) -> Bool;
+type RedirectWindowFn = unsafe extern "C" fn(*mut Display, Window, c_int);
+type NameWindowPixmapFn = unsafe extern "C" fn(*mut Display, Window) -> Pixmap;
+
+///
+/// The few libXcomposite entry points needed for window capture, loaded at
+/// runtime since `x11-dl` does not bind the extension
+#[allow(non_snake_case)]
+pub struct XComposite {
+    handle: *mut c_void,
+    pub XCompositeQueryExtension: QueryExtensionFn,
+    pub XCompositeRedirectWindow: RedirectWindowFn,
+    pub XCompositeUnredirectWindow: RedirectWindowFn,
+    pub XCompositeNameWindowPixmap: NameWindowPixmapFn,
+}
+
+impl XComposite {
+    pub fn open() -> Option<Self> {
+        unsafe {
+            let handle = [&b"libXcomposite.so.1\0"[..], &b"libXcomposite.so\0"[..]]
+                .iter()
+                .map(|name| libc::dlopen(name.as_ptr() as *const _, libc::RTLD_LAZY))
+                .find(|handle| !handle.is_null())?;
+            let symbol = |name: &[u8]| {
+                let name = CStr::from_bytes_with_nul(name).unwrap();
+                let sym = libc::dlsym(handle, name.as_ptr());
+                if sym.is_null() {
+                    None
+                } else {
+                    Some(sym)
+                }
+            };
+            let composite = (|| {
+                Some(XComposite {
+                    handle,
+                    XCompositeQueryExtension: std::mem::transmute::<*mut c_void, QueryExtensionFn>(
+                        symbol(b"XCompositeQueryExtension\0")?,
+                    ),
+                    XCompositeRedirectWindow: std::mem::transmute::<*mut c_void, RedirectWindowFn>(
+                        symbol(b"XCompositeRedirectWindow\0")?,
+                    ),
+                    XCompositeUnredirectWindow: std::mem::transmute::<*mut c_void, RedirectWindowFn>(
+                        symbol(b"XCompositeUnredirectWindow\0")?,
+                    ),
+                    XCompositeNameWindowPixmap: std::mem::transmute::<
+                        *mut c_void,
+                        NameWindowPixmapFn,
+                    >(symbol(
+                        b"XCompositeNameWindowPixmap\0",
+                    )?),
+                })
+            })();
+            if composite.is_none() {
+                libc::dlclose(handle);
+            }
+            composite
+        }
+    }
+}
+
+impl Drop for XComposite {
+    fn drop(&mut self) {
+        unsafe {
+            libc::dlclose(self.handle);
+        }
+    }
+}
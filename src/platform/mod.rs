@@ -1 +1,56 @@
+//! OS specific code lives below this module. The rest of the application only
+//! uses the items re-exported here, so `app` never needs to know which platform
+//! it is running on.
+
+use crate::app::capture_backend::{CaptureBackend, CaptureBackendKind};
+use crate::app::error::{CaptureError, CaptureResult};
+
+#[cfg(target_os = "windows")]
 pub mod windows;
+
+#[cfg(target_os = "linux")]
+pub mod linux;
+
+#[cfg(target_os = "windows")]
+pub use self::windows::window::{
+    config_window, config_window_builder, is_logical_window, set_click_through,
+};
+
+#[cfg(target_os = "linux")]
+pub use self::linux::window::{
+    config_window, config_window_builder, is_logical_window, set_click_through,
+};
+
+///
+/// Creates the native capture backend selected by `kind`, failing when it
+/// cannot capture anything here
+pub fn create_capture_backend(kind: &CaptureBackendKind) -> CaptureResult<Box<dyn CaptureBackend>> {
+    match kind {
+        CaptureBackendKind::Native => create_native_backend(),
+        #[cfg(target_os = "linux")]
+        CaptureBackendKind::X11 => self::linux::create_x11_backend(),
+        #[cfg(target_os = "linux")]
+        CaptureBackendKind::Wayland => self::linux::create_wayland_backend(),
+        #[allow(unreachable_patterns)]
+        _ => Err(CaptureError::Backend(format!(
+            "{:?} capture is not available on this platform",
+            kind
+        ))),
+    }
+}
+
+#[cfg(target_os = "windows")]
+fn create_native_backend() -> CaptureResult<Box<dyn CaptureBackend>> {
+    Ok(Box::new(
+        self::windows::capture_impl::GdiCaptureBackend::new(),
+    ))
+}
+
+#[cfg(target_os = "linux")]
+fn create_native_backend() -> CaptureResult<Box<dyn CaptureBackend>> {
+    if std::env::var_os("WAYLAND_DISPLAY").is_some() {
+        self::linux::create_wayland_backend()
+    } else {
+        self::linux::create_x11_backend()
+    }
+}
@@ -1,4 +1,7 @@
 use crate::app::canvas::Bound2;
+use crate::app::capture_backend::{CaptureBackend, PixelFormat, WindowSelector};
+use crate::app::error::{CaptureError, CaptureResult};
+use crate::app::frame_buffer;
 use image::{
     DynamicImage::ImageRgba8, GenericImage, GenericImageView, ImageBuffer, RgbImage, Rgba,
     RgbaImage,
@@ -106,30 +109,24 @@ impl CaptureImplWin {
 
     pub fn capture_image(&mut self) -> RgbaImage {
         self.capture_image_raw();
-        let img = ImageBuffer::from_fn(
+        let (width, height) = (
             self.bitmap_info.bmWidth as u32,
             self.bitmap_info.bmHeight as u32,
-            |x, y| {
-                let ind = (self.bitmap_info.bmHeight as u32 - 1u32 - y)
-                    * self.bitmap_info.bmWidth as u32
-                    + x;
-                let ind = ind as usize;
-                unsafe {
-                    let ptr = self.raw_data_bgra.as_ptr().add(ind * 4);
-                    image::Rgba([*ptr.add(0), *ptr.add(1), *ptr.add(2), *ptr.add(3)])
-                }
-            },
         );
-        img
+        let mut buf = self.raw_data_bgra.clone();
+        frame_buffer::flip_rows(&mut buf, width as usize * 4);
+        frame_buffer::swizzle_rb(&mut buf);
+        RgbaImage::from_raw(width, height, buf).expect("DIB size mismatch")
     }
 
     ///
-    /// Capture the specified region of screen to data buffer
-    pub fn capture_image_raw(&mut self) {
+    /// Capture the specified region of screen to data buffer, as bottom-up BGRA
+    /// rows. Returns whether both the blit and the copy succeeded.
+    pub fn capture_image_raw(&mut self) -> bool {
         let rect = self.bound.rect();
         unsafe {
             // transfer pixel data from screen
-            BitBlt(
+            let blitted = BitBlt(
                 self.hdc_mem,
                 0,
                 0,
@@ -139,10 +136,11 @@ impl CaptureImplWin {
                 rect.0 as i32,
                 rect.1 as i32,
                 SRCCOPY,
-            );
+            )
+            .as_bool();
 
             // copy it to memory
-            GetDIBits(
+            let lines = GetDIBits(
                 self.hdc_mem,
                 HBITMAP(self.bitmap.0),
                 0,
@@ -151,14 +149,186 @@ impl CaptureImplWin {
                 &mut self.bi as *mut _ as *mut BITMAPINFO,
                 DIB_RGB_COLORS,
             );
+            blitted && lines != 0
+        }
+    }
+}
+
+///
+/// Visible top level windows from the topmost down, leaving out the windows of
+/// this process such as the region selector
+fn top_level_windows() -> Vec<HWND> {
+    unsafe extern "system" fn push(hwnd: HWND, windows: LPARAM) -> BOOL {
+        (*(windows.0 as *mut Vec<HWND>)).push(hwnd);
+        BOOL(1)
+    }
+    let mut windows: Vec<HWND> = Vec::new();
+    unsafe {
+        EnumWindows(Some(push), LPARAM(&mut windows as *mut Vec<HWND> as isize));
+    }
+    windows
+        .into_iter()
+        .filter(|hwnd| unsafe {
+            let mut pid = 0;
+            GetWindowThreadProcessId(*hwnd, &mut pid);
+            IsWindowVisible(*hwnd).as_bool() && pid != std::process::id()
+        })
+        .collect()
+}
+
+fn window_title(hwnd: HWND) -> String {
+    unsafe {
+        let len = GetWindowTextLengthW(hwnd);
+        if len <= 0 {
+            return String::new();
+        }
+        let mut text = vec![0u16; len as usize + 1];
+        let copied = GetWindowTextW(hwnd, PWSTR(text.as_mut_ptr()), text.len() as i32);
+        String::from_utf16_lossy(&text[..copied.max(0) as usize])
+    }
+}
+
+///
+/// `CaptureBackend` over GDI. The GDI objects depend on the size of the region,
+/// so they are recreated each time a region is opened.
+pub struct GdiCaptureBackend {
+    pub hwnd: HWND,
+    pub capture: Option<CaptureImplWin>,
+}
+
+unsafe impl Send for GdiCaptureBackend {}
+
+impl GdiCaptureBackend {
+    ///
+    /// Captures from the DC of the whole desktop, which covers the virtual screen
+    /// of all monitors, so regions straddling them come out of a single blit
+    pub fn new() -> Self {
+        GdiCaptureBackend {
+            hwnd: HWND(0),
+            capture: None,
+        }
+    }
+}
 
-            for chunck in self.raw_data_bgra.chunks_mut(4) {
-                // convert bgra to rgba
-                let z = chunck[0];
-                chunck[0] = chunck[2];
-                chunck[2] = z;
+impl CaptureBackend for GdiCaptureBackend {
+    fn name(&self) -> &'static str {
+        "gdi"
+    }
+
+    fn open(&mut self, region: Bound2) -> CaptureResult<()> {
+        self.hwnd = HWND(0);
+        self.capture = None;
+        let capture = CaptureImplWin::new(self.hwnd, region);
+        if capture.bitmap.0 == 0 {
+            return Err(CaptureError::Backend(format!(
+                "failed to create a bitmap for {:?}",
+                region
+            )));
+        }
+        self.capture = Some(capture);
+        Ok(())
+    }
+
+    fn find_window(&mut self, selector: &WindowSelector) -> Option<u64> {
+        let hwnd = unsafe {
+            match selector {
+                WindowSelector::Id(id) => HWND(*id as isize),
+                // the same match as the X11 backend, rather than the whole
+                // title of `FindWindowW`
+                WindowSelector::Title(title) => top_level_windows()
+                    .into_iter()
+                    .find(|hwnd| window_title(*hwnd).contains(title.as_str()))
+                    .unwrap_or(HWND(0)),
+                WindowSelector::At(x, y) => {
+                    GetAncestor(WindowFromPoint(POINT { x: *x, y: *y }), GA_ROOT)
+                }
             }
+        };
+        if hwnd.0 == 0 {
+            None
+        } else {
+            Some(hwnd.0 as u64)
+        }
+    }
+
+    ///
+    /// The window DC is relative to the client area, so the capture follows the
+    /// window when it moves. Parts covered by other windows are not captured.
+    fn open_window(&mut self, window: u64) -> CaptureResult<()> {
+        let hwnd = HWND(window as isize);
+        let mut rect = RECT::default();
+        if unsafe { !GetClientRect(hwnd, &mut rect).as_bool() } {
+            return Err(CaptureError::Backend(format!(
+                "window {:#x} is gone",
+                window
+            )));
+        }
+        self.hwnd = hwnd;
+        self.capture = None;
+        self.capture = Some(CaptureImplWin::new(
+            hwnd,
+            Bound2::new((0, 0), (rect.right - rect.left, rect.bottom - rect.top)),
+        ));
+        Ok(())
+    }
+
+    fn size(&self) -> (u32, u32) {
+        self.capture.as_ref().map_or((0, 0), |c| {
+            (c.bitmap_info.bmWidth as u32, c.bitmap_info.bmHeight as u32)
+        })
+    }
+
+    fn frame_bound(&mut self) -> Bound2 {
+        let bound = self.capture.as_ref().map_or(Bound2::default(), |c| c.bound);
+        if self.hwnd.0 == 0 {
+            return bound;
+        }
+        let mut origin = POINT::default();
+        unsafe {
+            ClientToScreen(self.hwnd, &mut origin);
+        }
+        Bound2::new(
+            (origin.x, origin.y),
+            (
+                origin.x + bound.get_width() as i32,
+                origin.y + bound.get_height() as i32,
+            ),
+        )
+    }
+
+    fn pixel_format(&self) -> PixelFormat {
+        PixelFormat::Bgra8
+    }
+
+    fn cursor_position(&mut self) -> Option<(i32, i32)> {
+        let mut point = POINT::default();
+        unsafe {
+            GetCursorPos(&mut point)
+                .as_bool()
+                .then(|| (point.x, point.y))
+        }
+    }
+
+    fn grab_frame(&mut self, buf: &mut Vec<u8>) -> CaptureResult<()> {
+        let (width, height) = self.size();
+        let cap = self.capture.as_mut().expect("capture region is not opened");
+        if !cap.capture_image_raw() {
+            return Err(CaptureError::Backend(format!(
+                "failed to read {:?} of the screen",
+                cap.bound
+            )));
+        }
+
+        // DIBs are stored bottom-up
+        let row = width as usize * 4;
+        buf.resize(row * height as usize, 0);
+        for (dst, src) in buf
+            .chunks_exact_mut(row)
+            .zip(cap.raw_data_bgra.chunks_exact(row).rev())
+        {
+            dst.copy_from_slice(src);
         }
+        Ok(())
     }
 }
 
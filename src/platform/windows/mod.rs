@@ -1 +1,2 @@
 pub mod capture_impl;
+pub mod window;
@@ -0,0 +1,61 @@
+use glutin::window::{Window, WindowBuilder};
+use raw_window_handle::{HasRawWindowHandle, RawWindowHandle, Win32Handle};
+
+use windows::Win32::{Foundation::*, UI::WindowsAndMessaging::*};
+
+pub fn config_window_builder(wb: WindowBuilder) -> WindowBuilder {
+    wb
+}
+
+///
+/// Marks the window as a tool window so that it does not show up in the taskbar
+pub fn config_window(window: &Window) {
+    let handle = window.raw_window_handle();
+    unsafe {
+        match handle {
+            RawWindowHandle::Win32(Win32Handle {
+                hwnd, hinstance: _, ..
+            }) => {
+                let hwnd = HWND(hwnd as isize);
+                let mut exstyle = WINDOW_EX_STYLE(GetWindowLongPtrW(hwnd, GWL_EXSTYLE) as u32);
+                exstyle = exstyle | WS_EX_TOOLWINDOW;
+                SetWindowLongW(hwnd, GWL_EXSTYLE, exstyle.0 as i32);
+            }
+            _ => (),
+        }
+    }
+}
+
+///
+/// winit makes the process per-monitor DPI aware, so window and GDI coordinates
+/// are physical pixels of the virtual screen
+#[allow(unused)]
+pub fn is_logical_window(window: &Window) -> bool {
+    false
+}
+
+///
+/// Lets the mouse reach the windows below, so the desktop can be used while an
+/// overlay is shown. A transparent window only passes hit testing on when it is
+/// layered as well, which is fully opaque here so the GL alpha is kept.
+pub fn set_click_through(window: &Window, click_through: bool) {
+    let handle = window.raw_window_handle();
+    unsafe {
+        match handle {
+            RawWindowHandle::Win32(Win32Handle {
+                hwnd, hinstance: _, ..
+            }) => {
+                let hwnd = HWND(hwnd as isize);
+                let exstyle = GetWindowLongPtrW(hwnd, GWL_EXSTYLE) as u32;
+                let flags = (WS_EX_LAYERED | WS_EX_TRANSPARENT).0;
+                if click_through {
+                    SetWindowLongW(hwnd, GWL_EXSTYLE, (exstyle | flags) as i32);
+                    SetLayeredWindowAttributes(hwnd, 0, 255, LWA_ALPHA);
+                } else {
+                    SetWindowLongW(hwnd, GWL_EXSTYLE, (exstyle & !flags) as i32);
+                }
+            }
+            _ => (),
+        }
+    }
+}
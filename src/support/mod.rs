@@ -82,15 +82,15 @@ pub fn load(gl_context: &glutin::Context<PossiblyCurrent>) -> Gl {
             2,
             gl::FLOAT,
             0,
-            5 * std::mem::size_of::<f32>() as gl::types::GLsizei,
+            6 * std::mem::size_of::<f32>() as gl::types::GLsizei,
             std::ptr::null(),
         );
         gl.VertexAttribPointer(
             color_attrib as gl::types::GLuint,
-            3,
+            4,
             gl::FLOAT,
             0,
-            5 * std::mem::size_of::<f32>() as gl::types::GLsizei,
+            6 * std::mem::size_of::<f32>() as gl::types::GLsizei,
             (2 * std::mem::size_of::<f32>()) as *const () as *const _,
         );
         gl.EnableVertexAttribArray(pos_attrib as gl::types::GLuint);
@@ -126,6 +126,12 @@ impl Gl {
         }
     }
 
+    pub fn viewport(&self, width: u32, height: u32) {
+        unsafe {
+            self.gl.Viewport(0, 0, width as i32, height as i32);
+        }
+    }
+
     #[inline(always)]
     pub fn clear(&self, color: [f32; 4]) {
         unsafe {
@@ -183,20 +189,20 @@ static VERTEX_DATA: [f32; 15] = [
 ];
 
 #[rustfmt::skip]
-static RECT_DATA: [f32; 20] = [
-    -1.0, 1.0, 0.1, 0.1, 0.3,
-    1.0, 1.0, 0.1, 0.1, 0.3,
-    -1.0, -1.0, 0.1, 0.1, 0.3,
-    1.0, -1.0, 0.1, 0.1, 0.3,
+static RECT_DATA: [f32; 24] = [
+    -1.0, 1.0, 0.1, 0.1, 0.3, 0.1,
+    1.0, 1.0, 0.1, 0.1, 0.3, 0.1,
+    -1.0, -1.0, 0.1, 0.1, 0.3, 0.1,
+    1.0, -1.0, 0.1, 0.1, 0.3, 0.1,
 ];
 
 const VS_SRC: &'static [u8] = b"
 #version 430 core
 precision mediump float;
 in vec2 position;
-in vec3 color;
+in vec4 color;
 layout(location=0) uniform mat3 trans;
-out vec3 v_color;
+out vec4 v_color;
 void main() {
     gl_Position = vec4((trans*vec3(position, 1.0)).xy,0.0, 1.0);
     v_color = color;
@@ -206,9 +212,9 @@ void main() {
 const FS_SRC: &'static [u8] = b"
 #version 430 core
 precision mediump float;
-in vec3 v_color;
+in vec4 v_color;
 void main() {
-    gl_FragColor = vec4(v_color, 0.1);
+    gl_FragColor = v_color;
 }
 \0";
 